@@ -19,7 +19,7 @@ fn fwd() {
 
     let c = b.apply_fwd();
 
-    dbg!(c);
+    dbg!(&c);
 
     assert!(eq_f32(c.into(), 3.));
 }
@@ -37,14 +37,14 @@ fn fwd_looping() {
 
     let vl = l.apply_fwd();
 
-    dbg!(vl);
+    dbg!(&vl);
 
     assert!(eq_f32(vl.into(), 2048.));
 
     let mut g = l.fwd();
     let h = g.apply_fwd();
 
-    dbg!(h);
+    dbg!(&h);
 
     assert!(eq_f32(h.into(), 1024.));
 
@@ -65,7 +65,7 @@ fn rev() {
         .expect("l0 adjoint missing")
         .apply_rev();
 
-    dbg!(ret);
+    dbg!(&ret);
 
     assert!(eq_f32(ret.into(), 3.));
 }
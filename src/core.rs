@@ -4,35 +4,42 @@
 #![allow(non_snake_case)]
 
 // use std::borrow::{Borrow, BorrowMut};
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
 use std::cmp::{Eq, PartialEq};
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
 use std::ops::Deref;
 use std::rc::{Rc, Weak};
 #[cfg(test)]
 use std::sync::{atomic, Arc};
 
 #[derive(Clone, Debug)]
-pub struct PtrVWrap(pub Rc<RefCell<VWrap>>);
+pub struct PtrVWrap<T: Value = ValType>(pub Rc<RefCell<VWrap<T>>>);
 
-impl Hash for PtrVWrap {
+/// nodes hash and compare by the address of their allocation, which does not change
+/// when the node is mutated through the RefCell, so PtrVWrap is a sound map key and
+/// clippy::mutable_key_type is allowed where it is used as one
+impl<T: Value> Hash for PtrVWrap<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         let p = Rc::downgrade(&self.0);
         (Weak::as_raw(&p) as usize).hash(state);
     }
 }
 
-impl PartialEq for PtrVWrap {
+impl<T: Value> PartialEq for PtrVWrap<T> {
     fn eq(&self, other: &Self) -> bool {
         //decay both to Weak and compare
         Weak::ptr_eq(&Rc::downgrade(&self.0), &Rc::downgrade(&other.0))
     }
 }
 
-impl Eq for PtrVWrap {}
+impl<T: Value> Eq for PtrVWrap<T> {}
 
+use crate::taylor;
 use crate::valtype::ValType;
+use crate::value::Value;
 
 #[cfg(test)]
 lazy_static! {
@@ -46,27 +53,33 @@ fn get_id() -> i32 {
 
 /// wrapper for variable with recording of dependencies
 // #[derive(Debug)]
-pub struct VWrap {
+pub struct VWrap<T: Value = ValType> {
     /// input dependencies
-    pub inp: Vec<PtrVWrap>,
+    pub inp: Vec<PtrVWrap<T>>,
 
     /// source function
-    raw: Box<dyn FWrap>,
+    pub(crate) raw: Rc<dyn FWrap<T>>,
 
     /// evaluated value
-    pub val: Option<ValType>,
+    pub val: Option<T>,
 
     #[cfg(test)]
     pub id: i32,
 
     pub eval_g: bool,
 
-    /// adjoint accumulation expression
-    pub adj_accum: Option<PtrVWrap>,
+    /// tangent of an active node in forward mode, ones when not set
+    pub seed: Option<T>,
+
+    /// nodes using this one as input, weak to avoid reference cycles
+    consumers: Vec<Weak<RefCell<VWrap<T>>>>,
+
+    /// val needs to be recomputed because an upstream value changed
+    dirty: bool,
 }
 use std::fmt;
 
-impl fmt::Debug for VWrap {
+impl<T: Value> fmt::Debug for VWrap<T> {
     #[cfg(test)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(
@@ -85,243 +98,493 @@ impl fmt::Debug for VWrap {
     }
 }
 
+/// releases long input chains with an explicit stack instead of recursive drops
+impl<T: Value> Drop for VWrap<T> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.inp);
+        while let Some(n) = stack.pop() {
+            if Rc::strong_count(&n.0) == 1 {
+                if let Ok(mut inner) = n.0.try_borrow_mut() {
+                    stack.append(&mut inner.inp);
+                }
+            }
+        }
+    }
+}
+
 /// initializer functions
 #[allow(dead_code)]
-impl VWrap {
-    fn new(v: Box<dyn FWrap>) -> PtrVWrap {
+impl<T: Value> VWrap<T> {
+    fn new(v: Box<dyn FWrap<T>>) -> PtrVWrap<T> {
         PtrVWrap(Rc::new(RefCell::new(VWrap {
             inp: vec![],
-            raw: v,
+            raw: Rc::from(v),
             val: None,
             #[cfg(test)]
             id: get_id(),
             eval_g: false,
-            adj_accum: None,
+            seed: None,
+            consumers: vec![],
+            dirty: true,
         })))
     }
 
-    fn new_with_input(f: Box<dyn FWrap>, v: Vec<PtrVWrap>) -> PtrVWrap {
-        PtrVWrap(Rc::new(RefCell::new(VWrap {
-            inp: v,
-            raw: f,
+    pub(crate) fn new_with_input(f: Box<dyn FWrap<T>>, v: Vec<PtrVWrap<T>>) -> PtrVWrap<T> {
+        let mut a = VWrap::new(f);
+        a.set_inp(v);
+        a
+    }
+
+    /// node sharing an existing operation, used when rebuilding graphs
+    pub(crate) fn new_with_op(raw: Rc<dyn FWrap<T>>, v: Vec<PtrVWrap<T>>) -> PtrVWrap<T> {
+        let mut a = PtrVWrap(Rc::new(RefCell::new(VWrap {
+            inp: vec![],
+            raw,
             val: None,
             #[cfg(test)]
             id: get_id(),
             eval_g: false,
-            adj_accum: None,
-        })))
+            seed: None,
+            consumers: vec![],
+            dirty: true,
+        })));
+        a.set_inp(v);
+        a
+    }
+
+    /// tangent seed passed to link nodes, None for inactive nodes
+    pub(crate) fn seed_arg(&self, val: &T) -> Option<T> {
+        if self.eval_g {
+            Some(self.seed.clone().unwrap_or_else(|| val.ones_like()))
+        } else {
+            None
+        }
     }
 
-    fn new_with_val(v: Box<dyn FWrap>, val: ValType) -> PtrVWrap {
+    pub(crate) fn new_with_val(v: Box<dyn FWrap<T>>, val: T) -> PtrVWrap<T> {
         PtrVWrap(Rc::new(RefCell::new(VWrap {
             inp: vec![],
-            raw: v,
+            raw: Rc::from(v),
             val: Some(val),
             #[cfg(test)]
             id: get_id(),
             eval_g: false,
-            adj_accum: None,
+            seed: None,
+            consumers: vec![],
+            dirty: true,
         })))
     }
 }
 
-impl PtrVWrap {
-    fn set_inp(&mut self, v: Vec<PtrVWrap>) {
+impl<T: Value> PtrVWrap<T> {
+    fn set_inp(&mut self, v: Vec<PtrVWrap<T>>) {
+        for i in v.iter() {
+            let mut i = i.0.deref().borrow_mut();
+            //drop entries of dropped consumers whenever the list is full,
+            //leaves that never change would otherwise keep one per graph built on them
+            if i.consumers.len() == i.consumers.capacity() {
+                i.consumers.retain(|w| w.strong_count() > 0);
+                let n = i.consumers.len();
+                i.consumers.reserve(n);
+            }
+            i.consumers.push(Rc::downgrade(&self.0));
+        }
         self.0.deref().borrow_mut().inp = v;
+        self.invalidate();
     }
 
-    pub fn set_val(&mut self, v: ValType) {
+    pub fn set_val(&mut self, v: T) {
         self.0.deref().borrow_mut().val = Some(v);
+        self.0.deref().borrow_mut().dirty = false;
+        self.invalidate_consumers();
     }
 
-    /// forward mode (tanget-linear)
-    pub fn apply_fwd(&mut self) -> ValType {
-        let mut args: Vec<(ValType, bool)> = vec![];
+    /// marks the value of this node and of all downstream nodes for recomputation
+    fn invalidate(&self) {
+        self.0.deref().borrow_mut().dirty = true;
+        self.invalidate_consumers();
+    }
 
-        //recursive apply
-        for i in self.0.deref().borrow_mut().inp.iter_mut() {
-            let val = i.apply_fwd();
-            args.push((val, i.0.deref().borrow().eval_g));
+    /// marks the values of all downstream nodes for recomputation
+    ///
+    /// a clean node only has clean inputs, so the walk stops at nodes already dirty
+    fn invalidate_consumers(&self) {
+        let mut stack = vec![self.0.clone()];
+        while let Some(n) = stack.pop() {
+            let mut n = n.deref().borrow_mut();
+            n.consumers.retain(|c| c.upgrade().is_some());
+            for c in n.consumers.iter() {
+                let c = c.upgrade().expect("consumer dropped");
+                if !c.deref().borrow().dirty {
+                    c.deref().borrow_mut().dirty = true;
+                    stack.push(c);
+                }
+            }
         }
+    }
 
-        let v = self.0.deref().borrow().raw.f()(args, self.0.deref().borrow().val);
-
-        self.0.deref().borrow_mut().val = Some(v);
+    /// value computed since the last upstream change
+    pub(crate) fn cached(&self) -> Option<T> {
+        let n = self.0.deref().borrow();
+        if n.dirty {
+            None
+        } else {
+            n.val.clone()
+        }
+    }
 
+    /// evaluates the node given the values of its inputs and caches the result
+    pub(crate) fn compute(&self, args: Vec<(T, Option<T>)>) -> T {
+        let v = self.0.deref().borrow().raw.f()(args, self.0.deref().borrow().val.clone());
+        let mut n = self.0.deref().borrow_mut();
+        n.val = Some(v.clone());
+        n.dirty = false;
         v
     }
 
-    /// reverse mode (adjoint)
-    fn apply_rev_recurse(&mut self) -> ValType {
-        let mut args: Vec<(ValType, bool)> = vec![];
-
-        //recursive apply
-        for i in self.0.deref().borrow_mut().inp.iter_mut() {
-            let val = i.apply_rev_recurse();
-            let temp = i.0.deref().borrow().eval_g;
-            args.push((val, temp));
+    /// forward mode (tanget-linear)
+    ///
+    /// values are cached, only nodes downstream of a change are recomputed
+    pub fn apply_fwd(&mut self) -> T {
+        if let Some(v) = self.cached() {
+            return v;
         }
 
-        let v = self.0.deref().borrow().raw.f()(args, self.0.deref().borrow().val);
-
-        self.0.deref().borrow_mut().val = Some(v);
+        //explicit stack post-order over dirty nodes, deep graphs do not recurse
+        let mut stack = vec![(self.clone(), false)];
+        while let Some((n, expanded)) = stack.pop() {
+            if expanded {
+                if n.cached().is_none() {
+                    let args: Vec<(T, Option<T>)> =
+                        n.0.deref()
+                            .borrow()
+                            .inp
+                            .iter()
+                            .map(|i| {
+                                let i = i.0.deref().borrow();
+                                let v = i.val.clone().expect("input not evaluated");
+                                let s = i.seed_arg(&v);
+                                (v, s)
+                            })
+                            .collect();
+                    n.compute(args);
+                }
+                continue;
+            }
+            if n.cached().is_some() {
+                continue;
+            }
+            stack.push((n.clone(), true));
+            for i in n.0.deref().borrow().inp.iter() {
+                if i.cached().is_none() {
+                    stack.push((i.clone(), false));
+                }
+            }
+        }
 
-        v
+        self.cached().expect("value missing")
     }
 
     /// reverse mode (adjoint)
-    pub fn apply_rev(&mut self) -> ValType {
-        let v = self.apply_rev_recurse();
-
-        v
+    pub fn apply_rev(&mut self) -> T {
+        self.apply_fwd()
     }
 
     /// create adjoint graph starting from current variable and go through input dependencies
     ///
-    /// resulting sensitivity graphs are propagated to leaf nodes' adjoint accumulation
-    /// where it can be collected
-    pub fn rev(&self) -> HashMap<PtrVWrap, PtrVWrap> {
-        use std::collections::VecDeque;
+    /// resulting sensitivity graphs are accumulated per sweep and returned for the leaf
+    /// nodes, the graph is not modified so sweeps may be nested or interleaved
+    #[allow(clippy::mutable_key_type)]
+    pub fn rev(&self) -> HashMap<PtrVWrap<T>, PtrVWrap<T>> {
+        self.rev_wrt(&[])
+    }
 
-        let mut q = VecDeque::new();
+    /// like rev() but also returns the adjoints of the given intermediate nodes
+    #[allow(clippy::mutable_key_type)]
+    pub fn rev_wrt(&self, wrt: &[PtrVWrap<T>]) -> HashMap<PtrVWrap<T>, PtrVWrap<T>> {
+        //initialization of sensitity=1 for starting node, shaped like its value
+        rev_seeded(
+            std::slice::from_ref(self),
+            vec![OnesLike(self.clone())],
+            wrt,
+        )
+    }
 
-        let mut adjoints_collected = HashMap::new();
+    /// create tangent-linear starting from current variable
+    ///
+    /// tangents of the inputs are built first in topological order and cached for the
+    /// duration of the sweep, so the fwd() calls made by tangent rules do not recurse
+    pub fn fwd(&self) -> PtrVWrap<T> {
+        if let Some(t) = fwd_cache_get(self) {
+            return t;
+        }
 
-        //initialization of sensitity=1 for starting node
-        self.0.deref().borrow_mut().adj_accum = Some(VWrap::new(OpOne::new()));
+        let _sweep = FwdSweep::<T>::start();
 
-        q.push_back(self.clone());
+        for n in topo_order(std::slice::from_ref(self)) {
+            if fwd_cache_get(&n).is_none() {
+                let mut g = n.0.deref().borrow().raw.tangent();
+                let inp = n.0.deref().borrow().inp.clone();
+                let t = g(inp, &n);
+                fwd_cache_insert(n, t);
+            }
+        }
 
-        let mut visited: HashSet<PtrVWrap> = HashSet::new();
+        fwd_cache_get(self).expect("tangent missing")
+    }
 
-        //breadth-first
-        while !q.is_empty() {
-            let n = q.pop_front().unwrap();
+    /// indicator in fwd propagation
+    pub fn active(&mut self) -> Self {
+        self.set_seed(true, None);
+        self.clone()
+    }
 
-            if visited.contains(&n) {
-                //skip already traversed nodes
-                continue;
-            }
+    pub fn inactive(&mut self) -> Self {
+        self.set_seed(false, None);
+        self.clone()
+    }
 
-            if n.0.deref().borrow_mut().adj_accum.is_none() {
-                n.0.deref().borrow_mut().adj_accum = Some(VWrap::new(OpZero::new()));
-            }
+    /// active with the given tangent instead of ones
+    ///
+    /// seeding several leaves gives the directional derivative along the seeds in one fwd() pass
+    pub fn seed(&mut self, seed: T) -> Self {
+        self.set_seed(true, Some(seed));
+        self.clone()
+    }
 
-            //delegate adjoint calc to operation
-            let adjoints = {
-                let mut f = n.0.deref().borrow().raw.adjoint();
-                f(
-                    n.0.deref().borrow().inp.clone(),
-                    n.0.deref()
-                        .borrow()
-                        .adj_accum
-                        .as_ref()
-                        .expect("adj_accum empty")
-                        .clone(),
-                    &n,
-                )
-            };
-
-            assert_eq!(adjoints.len(), n.0.deref().borrow().inp.len());
-
-            //propagate adjoints to inputs
-            let l = adjoints.len();
-            for idx in 0..l {
-                if n.0.deref().borrow_mut().inp[idx]
-                    .0
-                    .deref()
-                    .borrow_mut()
-                    .adj_accum
-                    .is_none()
-                {
-                    n.0.deref().borrow_mut().inp[idx]
-                        .0
-                        .deref()
-                        .borrow_mut()
-                        .adj_accum = Some(VWrap::new(OpZero::new()));
-                }
+    /// current indicator and seed
+    #[cfg(test)]
+    pub(crate) fn seed_state(&self) -> (bool, Option<T>) {
+        let n = self.0.deref().borrow();
+        (n.eval_g, n.seed.clone())
+    }
 
-                let temp = n.0.deref().borrow().inp[idx]
-                    .0
-                    .deref()
-                    .borrow()
-                    .adj_accum
-                    .as_ref()
-                    .unwrap()
-                    .clone();
-
-                n.0.deref().borrow_mut().inp[idx]
-                    .0
-                    .deref()
-                    .borrow_mut()
-                    .adj_accum = Some(Add(temp, adjoints[idx].clone()));
+    fn set_seed(&mut self, eval_g: bool, seed: Option<T>) {
+        let unchanged = {
+            let n = self.0.deref().borrow();
+            n.eval_g == eval_g && n.seed.is_none() && seed.is_none()
+        };
+        if !unchanged {
+            {
+                let mut n = self.0.deref().borrow_mut();
+                n.eval_g = eval_g;
+                n.seed = seed;
             }
+            //consumers read the seed of their inputs
+            self.invalidate_consumers();
+        }
+    }
+}
 
-            //reset adjoint accumulation for current node to zero
-            if !n.0.deref().borrow().inp.is_empty() {
-                //reset adjoints for internal nodes
-                n.0.deref().borrow_mut().adj_accum = None;
-            } else {
-                //collect adjoints for leaf nodes
-                let adj = n.0.deref().borrow_mut().adj_accum.take();
-                adjoints_collected.insert(n.clone(), adj.expect("leaf adjoint missing"));
-            }
+thread_local! {
+    /// tangents built during the current fwd() sweep, one map of FwdTangents per value type
+    static FWD_CACHE: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
 
-            //do adjoints for inputs
-            for i in n.0.deref().borrow().inp.iter() {
-                q.push_back(i.clone());
+/// tangent of each node, the keys keep the nodes alive for the duration of the sweep
+type FwdTangents<T> = HashMap<PtrVWrap<T>, PtrVWrap<T>>;
+
+/// marks a running fwd() sweep, the outermost one clears the cache when dropped,
+/// also when a tangent rule panics
+struct FwdSweep<T: Value> {
+    outer: bool,
+    _t: PhantomData<T>,
+}
+
+impl<T: Value> FwdSweep<T> {
+    fn start() -> Self {
+        let outer = FWD_CACHE.with(|c| {
+            let mut c = c.borrow_mut();
+            let id = TypeId::of::<T>();
+            let outer = !c.contains_key(&id);
+            if outer {
+                c.insert(id, Box::new(FwdTangents::<T>::new()));
             }
+            outer
+        });
+        FwdSweep {
+            outer,
+            _t: PhantomData,
+        }
+    }
+}
 
-            visited.insert(n.clone());
+impl<T: Value> Drop for FwdSweep<T> {
+    fn drop(&mut self) {
+        if self.outer {
+            //nodes are dropped after the borrow ends
+            let tangents = FWD_CACHE.with(|c| c.borrow_mut().remove(&TypeId::of::<T>()));
+            drop(tangents);
         }
+    }
+}
+
+fn fwd_cache_get<T: Value>(n: &PtrVWrap<T>) -> Option<PtrVWrap<T>> {
+    FWD_CACHE.with(|c| {
+        c.borrow()
+            .get(&TypeId::of::<T>())
+            .and_then(|m| m.downcast_ref::<FwdTangents<T>>())
+            .and_then(|m| m.get(n).cloned())
+    })
+}
+
+fn fwd_cache_insert<T: Value>(n: PtrVWrap<T>, t: PtrVWrap<T>) {
+    FWD_CACHE.with(|c| {
+        c.borrow_mut()
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|m| m.downcast_mut::<FwdTangents<T>>())
+            .expect("fwd sweep not started")
+            .insert(n, t);
+    })
+}
+
+/// adjoint expressions accumulated during one reverse sweep
+///
+/// owned by the sweep, so sweeps over overlapping graphs do not interfere
+struct AdjointAccum<T: Value> {
+    accum: HashMap<PtrVWrap<T>, PtrVWrap<T>>,
+}
 
-        adjoints_collected
+impl<T: Value> AdjointAccum<T> {
+    fn new() -> Self {
+        AdjointAccum {
+            accum: HashMap::new(),
+        }
     }
 
-    /// create tangent-linear starting from current variable
-    pub fn fwd(&self) -> PtrVWrap {
-        let mut g = self.0.deref().borrow().raw.tangent();
-        let ret = g(self.0.deref().borrow().inp.clone(), self);
-        ret
+    fn add(&mut self, n: &PtrVWrap<T>, adj: PtrVWrap<T>) {
+        let a = match self.accum.remove(n) {
+            Some(temp) => Add(temp, adj),
+            None => adj,
+        };
+        self.accum.insert(n.clone(), a);
     }
 
-    /// indicator in fwd propagation
-    pub fn active(&mut self) -> Self {
-        self.0.deref().borrow_mut().eval_g = true;
-        self.clone()
+    fn take(&mut self, n: &PtrVWrap<T>) -> PtrVWrap<T> {
+        self.accum.remove(n).expect("adjoint missing")
     }
+}
 
-    pub fn inactive(&mut self) -> Self {
-        self.0.deref().borrow_mut().eval_g = false;
-        self.clone()
+/// reverse sweep starting at several nodes at once, each with its adjoint seed
+///
+/// the adjoints collected at the leaves and at the wrt nodes are those of the seed
+/// weighted sum of the roots
+#[allow(clippy::mutable_key_type)]
+pub(crate) fn rev_seeded<T: Value>(
+    roots: &[PtrVWrap<T>],
+    seeds: Vec<PtrVWrap<T>>,
+    wrt: &[PtrVWrap<T>],
+) -> HashMap<PtrVWrap<T>, PtrVWrap<T>> {
+    assert_eq!(roots.len(), seeds.len(), "one seed per root");
+    let wrt: HashSet<&PtrVWrap<T>> = wrt.iter().collect();
+    let mut adjoints_collected = HashMap::new();
+    let mut accum = AdjointAccum::new();
+
+    for (r, seed) in roots.iter().zip(seeds) {
+        accum.add(r, seed);
+    }
+
+    //in reverse topological order all consumers of a node are processed before it,
+    //so its adjoint is complete when it is propagated to its inputs
+    for n in rev_topo_order(roots).into_iter().rev() {
+        let out_adj = accum.take(&n);
+
+        if n.0.deref().borrow().inp.is_empty() {
+            //collect adjoints for leaf nodes
+            adjoints_collected.insert(n.clone(), out_adj);
+            continue;
+        }
+
+        //adjoint of an intermediate node is complete here as well
+        if wrt.contains(&n) {
+            adjoints_collected.insert(n.clone(), out_adj.clone());
+        }
+
+        //delegate adjoint calc to operation
+        let inp = n.0.deref().borrow().inp.clone();
+        let adjoints = {
+            let mut f = n.0.deref().borrow().raw.adjoint();
+            f(inp.clone(), out_adj, &n)
+        };
+
+        assert_eq!(adjoints.len(), inp.len());
+
+        //propagate adjoints to inputs, except through edges that stop gradients
+        let raw = n.0.deref().borrow().raw.clone();
+        for (k, (i, adj)) in inp.iter().zip(adjoints).enumerate() {
+            if !raw.stops_gradient(k) {
+                accum.add(i, adj);
+            }
+        }
     }
 
-    pub fn adjoint(&self) -> Option<PtrVWrap> {
-        self.0.deref().borrow().adj_accum.clone()
+    adjoints_collected
+}
+
+/// nodes reachable from roots, each node placed after all of its inputs
+pub(crate) fn topo_order<T: Value>(roots: &[PtrVWrap<T>]) -> Vec<PtrVWrap<T>> {
+    topo_order_by(roots, false)
+}
+
+/// like topo_order but only following edges that adjoints flow back through,
+/// nodes behind a stopped gradient are left out of reverse sweeps
+pub(crate) fn rev_topo_order<T: Value>(roots: &[PtrVWrap<T>]) -> Vec<PtrVWrap<T>> {
+    topo_order_by(roots, true)
+}
+
+#[allow(clippy::mutable_key_type)]
+fn topo_order_by<T: Value>(roots: &[PtrVWrap<T>], differentiable_only: bool) -> Vec<PtrVWrap<T>> {
+    let mut order = vec![];
+    let mut visited: HashSet<PtrVWrap<T>> = HashSet::new();
+
+    //iterative post-order depth-first search
+    let mut stack: Vec<(PtrVWrap<T>, bool)> = roots.iter().map(|n| (n.clone(), false)).collect();
+    while let Some((n, expanded)) = stack.pop() {
+        if expanded {
+            order.push(n);
+            continue;
+        }
+        if !visited.insert(n.clone()) {
+            continue;
+        }
+        stack.push((n.clone(), true));
+        let node = n.0.deref().borrow();
+        for (k, i) in node.inp.iter().enumerate().rev() {
+            if differentiable_only && node.raw.stops_gradient(k) {
+                continue;
+            }
+            if !visited.contains(i) {
+                stack.push((i.clone(), false));
+            }
+        }
     }
+    order
+}
+
+/// type inspection of operations behind dyn FWrap,
+/// call as `AsAny::as_any(&*raw)` so the blanket impl is not picked for the pointer
+pub(crate) trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+}
 
-    pub fn reset_adjoint(&mut self) {
-        self.0.deref().borrow_mut().adj_accum = None;
+impl<X: Any> AsAny for X {
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
 /// wrapper for function
-trait FWrap: std::fmt::Debug {
-    fn new() -> Box<dyn FWrap>
+pub(crate) trait FWrap<T: Value>: std::fmt::Debug + AsAny {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized;
 
     /// creates a function to evaluate given values
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType>;
-
-    /// creates a function to evaluate given values for reverse pass
-    fn f_rev(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        self.f()
-    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T>;
 
     /// creates linear tangent function with given input dependencies and returns wrapped variable
     /// used in forward mode
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap>;
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>>;
 
     /// creates function to compute the adjoint for the input dependencies
     /// used in reverse mode
@@ -329,28 +592,71 @@ trait FWrap: std::fmt::Debug {
         &self,
     ) -> Box<
         dyn FnMut(
-            Vec<PtrVWrap>, /*inputs*/
-            PtrVWrap,      /*accumulated adjoint*/
-            &PtrVWrap,     /*self*/
-        ) -> Vec<PtrVWrap>,
+            Vec<PtrVWrap<T>>, /*inputs*/
+            PtrVWrap<T>,      /*accumulated adjoint*/
+            &PtrVWrap<T>,     /*self*/
+        ) -> Vec<PtrVWrap<T>>,
     >;
+
+    /// value only depends on the values of the inputs,
+    /// so equal nodes can be merged and constant inputs folded
+    fn pure(&self) -> bool {
+        true
+    }
+
+    /// input k does not receive an adjoint, reverse sweeps skip that edge
+    /// instead of propagating zeros through the subgraph behind it
+    fn stops_gradient(&self, _k: usize) -> bool {
+        false
+    }
+
+    /// adjoint values for the input dependencies given plain values
+    /// used in numeric reverse mode
+    ///
+    /// the default builds the symbolic adjoint on constant nodes and evaluates it,
+    /// built-in ops override it to skip graph construction
+    fn adjoint_val(&self, inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        let inp = inputs.iter().map(|v| Const(v.clone())).collect();
+        let adj = self.adjoint()(inp, Const(out_adj.clone()), &Const(output.clone()));
+        crate::grad::eval(&adj)
+    }
+
+    /// tangent value given plain values and the tangents of the inputs
+    /// used in vector forward mode
+    ///
+    /// the default builds the symbolic tangent on leaves seeded with the input
+    /// tangents and evaluates it, built-in ops override it to skip graph construction
+    fn tangent_val(&self, inputs: &[T], output: &T, tangents: &[T]) -> T {
+        let inp = inputs
+            .iter()
+            .zip(tangents)
+            .map(|(v, t)| Leaf(v.clone()).seed(t.clone()))
+            .collect();
+        let t = self.tangent()(inp, &Const(output.clone()));
+        crate::grad::eval(&[t]).pop().expect("tangent missing")
+    }
+
+    /// Taylor coefficients of the output, None to derive them from the tangent rule
+    fn taylor_val(&self, _inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        None
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
-struct OpMul {}
+pub(crate) struct OpMul {}
 #[derive(Debug, Clone, Copy)]
-struct OpAdd {}
+pub(crate) struct OpAdd {}
 #[derive(Debug, Clone, Copy)]
 struct OpLeaf {}
 #[derive(Debug, Clone, Copy)]
-struct OpOne {}
+pub(crate) struct OpOne {}
 /// special link to variable of interest for gradient calc
 #[derive(Debug, Clone, Copy)]
 struct OpLink {}
 #[derive(Debug, Clone, Copy)]
-struct OpZero {}
+pub(crate) struct OpZero {}
 #[derive(Debug, Clone, Copy)]
-struct OpConst {}
+pub(crate) struct OpConst {}
 #[derive(Debug, Clone, Copy)]
 struct OpSin {}
 #[derive(Debug, Clone, Copy)]
@@ -358,37 +664,112 @@ struct OpCos {}
 #[derive(Debug, Clone, Copy)]
 struct OpTan {}
 #[derive(Debug, Clone, Copy)]
-struct OpPow {}
+pub(crate) struct OpPow {}
 #[derive(Debug, Clone, Copy)]
 struct OpExp {}
 #[derive(Debug, Clone, Copy)]
 struct OpLn {}
 #[derive(Debug, Clone, Copy)]
-struct OpDiv {}
+pub(crate) struct OpDiv {}
+#[derive(Debug, Clone, Copy)]
+struct OpMatMul {}
+#[derive(Debug, Clone, Copy)]
+struct OpTranspose {}
+/// sum over all elements or over an axis
+#[derive(Debug, Clone, Copy)]
+struct OpSum {
+    axis: Option<usize>,
+}
+/// mean over all elements or over an axis
+#[derive(Debug, Clone, Copy)]
+struct OpMean {
+    axis: Option<usize>,
+}
+/// number of elements reduced by OpSum or OpMean, not differentiable
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpCount {
+    axis: Option<usize>,
+}
+#[derive(Debug, Clone)]
+struct OpReshape {
+    shape: Vec<usize>,
+}
+/// reshape first input to the shape of the second input
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpReshapeLike {}
+/// broadcast first input to the shape of the second input
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpBroadcastLike {}
+/// sum-reduce first input to the shape of the second input
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpSumLike {}
+#[derive(Debug, Clone, Copy)]
+struct OpInsertAxis {
+    axis: usize,
+}
+#[derive(Debug, Clone, Copy)]
+struct OpSqrt {}
+#[derive(Debug, Clone, Copy)]
+struct OpAbs {}
+#[derive(Debug, Clone, Copy)]
+struct OpTanh {}
+#[derive(Debug, Clone, Copy)]
+struct OpSinh {}
+#[derive(Debug, Clone, Copy)]
+struct OpCosh {}
+#[derive(Debug, Clone, Copy)]
+struct OpAsin {}
+#[derive(Debug, Clone, Copy)]
+struct OpAcos {}
+#[derive(Debug, Clone, Copy)]
+struct OpAtan {}
+#[derive(Debug, Clone, Copy)]
+struct OpLog10 {}
+#[derive(Debug, Clone, Copy)]
+struct OpLog2 {}
+#[derive(Debug, Clone, Copy)]
+struct OpSigmoid {}
+#[derive(Debug, Clone, Copy)]
+struct OpSoftplus {}
+#[derive(Debug, Clone, Copy)]
+struct OpErf {}
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpNeg {}
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpSub {}
+#[derive(Debug, Clone, Copy)]
+struct OpAtan2 {}
+#[derive(Debug, Clone, Copy)]
+struct OpMax {}
+#[derive(Debug, Clone, Copy)]
+struct OpMin {}
+/// -1, 0 or 1, not differentiable
+#[derive(Debug, Clone, Copy)]
+struct OpSign {}
+/// identity in value, blocks derivatives
+#[derive(Debug, Clone, Copy)]
+struct OpStopGradient {}
+/// scalar constant in the floating point type of the input
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpScalarLike {
+    v: f64,
+}
 
-impl FWrap for OpMul {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpMul {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpMul {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _: Option<T>| {
             assert!(x.len() == 2);
-            match (x[0].0, x[1].0) {
-                (ValType::F(v0), ValType::F(v1)) => ValType::F(v0 * v1),
-                (ValType::I(v0), ValType::I(v1)) => ValType::I(v0 * v1),
-                (ValType::F(v0), ValType::I(v1)) => ValType::F(v0 * v1 as f32),
-                (ValType::I(v0), ValType::F(v1)) => ValType::F(v0 as f32 * v1),
-                _ => {
-                    panic!("type not supported");
-                }
-            }
+            x[0].0.mul(&x[1].0)
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _: &PtrVWrap<T>| {
             assert!(args.len() == 2);
 
             //apply chain rule: (xy)' = x'y + xy'
@@ -402,40 +783,50 @@ impl FWrap for OpMul {
             VWrap::new_with_input(OpAdd::new(), vec![m1, m2])
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 2);
                 vec![
-                    Mul(inputs[1].clone(), out_adj.clone()),
-                    Mul(inputs[0].clone(), out_adj),
+                    SumLike(Mul(inputs[1].clone(), out_adj.clone()), inputs[0].clone()),
+                    SumLike(Mul(inputs[0].clone(), out_adj), inputs[1].clone()),
                 ]
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![
+            inputs[1].mul(out_adj).sum_to(&inputs[0].shape()),
+            inputs[0].mul(out_adj).sum_to(&inputs[1].shape()),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0]
+            .mul(&inputs[1])
+            .add(&inputs[0].mul(&tangents[1]))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        Some(taylor::mul(&inputs[0], &inputs[1]))
+    }
 }
 
-impl FWrap for OpAdd {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpAdd {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpAdd {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _: Option<T>| {
             assert_eq!(x.len(), 2);
-            match (x[0].0, x[1].0) {
-                (ValType::F(v0), ValType::F(v1)) => ValType::F(v0 + v1),
-                (ValType::I(v0), ValType::I(v1)) => ValType::I(v0 + v1),
-                _ => {
-                    panic!("type not supported");
-                }
-            }
+            x[0].0.add(&x[1].0)
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _: &PtrVWrap<T>| {
             //apply rule: (a+b+c+...)' = a'+b'+c'+...
 
             let mut inp_grad = vec![];
@@ -462,34 +853,55 @@ impl FWrap for OpAdd {
             inp_grad[count - 1].clone()
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 2);
-                vec![out_adj.clone(), out_adj]
+                //reduce broadcasted adjoint back to the shape of each input
+                vec![
+                    SumLike(out_adj.clone(), inputs[0].clone()),
+                    SumLike(out_adj, inputs[1].clone()),
+                ]
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        //reduce broadcasted adjoint back to the shape of each input
+        vec![
+            out_adj.sum_to(&inputs[0].shape()),
+            out_adj.sum_to(&inputs[1].shape()),
+        ]
+    }
+    fn tangent_val(&self, _inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].add(&tangents[1])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        Some(taylor::add(&inputs[0], &inputs[1]))
+    }
 }
 
-impl FWrap for OpLeaf {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpLeaf {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpLeaf {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |_x: Vec<(ValType, bool)>, v: Option<ValType>| v.expect("leaf value missing"))
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |_x: Vec<(T, Option<T>)>, v: Option<T>| v.expect("leaf value missing"))
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |_args: Vec<PtrVWrap>, self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |_args: Vec<PtrVWrap<T>>, self_ptr: &PtrVWrap<T>| {
             VWrap::new_with_input(OpLink::new(), vec![self_ptr.clone()])
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, _out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 0);
                 vec![]
             },
@@ -498,56 +910,67 @@ impl FWrap for OpLeaf {
 }
 
 /// special construct for representing derivative of a variable created in tangent-linear pass
-impl FWrap for OpLink {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpLink {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpLink {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 1);
-            if x[0].1 {
-                //indicator for calculating gradient of the linked variable
-                ValType::F(1.)
-            } else {
-                ValType::F(0.)
+            //seed of the linked variable when it is active
+            match &x[0].1 {
+                Some(seed) => seed.clone(),
+                None => x[0].0.zeros_like(),
             }
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |_args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
-            VWrap::new_with_val(OpZero::new(), ValType::F(0.))
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            ZerosLike(args[0].clone())
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, _out_adj: PtrVWrap, _cur: &PtrVWrap| {
-                vec![VWrap::new_with_val(OpZero::new(), ValType::F(0.)); inputs.len()]
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                inputs.iter().map(|i| ZerosLike(i.clone())).collect()
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, _out_adj: &T) -> Vec<T> {
+        inputs.iter().map(|i| i.zeros_like()).collect()
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, _tangents: &[T]) -> T {
+        output.zeros_like()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::constant(output, inputs[0].len()))
+    }
 }
 
-impl FWrap for OpConst {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpConst {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpConst {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |_x: Vec<(ValType, bool)>, v: Option<ValType>| v.expect("leaf value missing"))
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |_x: Vec<(T, Option<T>)>, v: Option<T>| v.expect("leaf value missing"))
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |_args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
-            VWrap::new_with_val(OpZero::new(), ValType::F(0.))
-        })
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |_args: Vec<PtrVWrap<T>>, self_ptr: &PtrVWrap<T>| ZerosLike(self_ptr.clone()))
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, _out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 0);
                 vec![]
             },
@@ -555,199 +978,263 @@ impl FWrap for OpConst {
     }
 }
 
-impl FWrap for OpOne {
-    fn new() -> Box<dyn FWrap>
+/// constant 1, shaped like the optional input
+impl<T: Value> FWrap<T> for OpOne {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpOne {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |_x: Vec<(ValType, bool)>, _v: Option<ValType>| ValType::F(1.))
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(
+            move |x: Vec<(T, Option<T>)>, _v: Option<T>| match x.first() {
+                Some((shape, _)) => shape.ones_like(),
+                None => T::from_i32(1),
+            },
+        )
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |_args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
-            VWrap::new_with_val(OpZero::new(), ValType::F(0.))
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            VWrap::new_with_input(OpZero::new(), args)
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, _out_adj: PtrVWrap, _cur: &PtrVWrap| {
-                assert_eq!(inputs.len(), 0);
-                vec![]
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert!(inputs.len() <= 1);
+                inputs.iter().map(|i| ZerosLike(i.clone())).collect()
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, _out_adj: &T) -> Vec<T> {
+        inputs.iter().map(|i| i.zeros_like()).collect()
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, _tangents: &[T]) -> T {
+        output.zeros_like()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::constant(output, inputs[0].len()))
+    }
 }
 
-impl FWrap for OpZero {
-    fn new() -> Box<dyn FWrap>
+/// constant 0, shaped like the optional input
+impl<T: Value> FWrap<T> for OpZero {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpZero {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |_x: Vec<(ValType, bool)>, _v: Option<ValType>| {
-            //todo
-            ValType::F(0.)
-        })
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(
+            move |x: Vec<(T, Option<T>)>, _v: Option<T>| match x.first() {
+                Some((shape, _)) => shape.zeros_like(),
+                None => T::from_i32(0),
+            },
+        )
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |_args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
-            VWrap::new_with_val(OpZero::new(), ValType::F(0.))
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            VWrap::new_with_input(OpZero::new(), args)
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, _out_adj: PtrVWrap, _cur: &PtrVWrap| {
-                assert_eq!(inputs.len(), 0);
-                vec![]
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert!(inputs.len() <= 1);
+                inputs.iter().map(|i| ZerosLike(i.clone())).collect()
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, _out_adj: &T) -> Vec<T> {
+        inputs.iter().map(|i| i.zeros_like()).collect()
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, _tangents: &[T]) -> T {
+        output.zeros_like()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::constant(output, inputs[0].len()))
+    }
 }
 
-impl FWrap for OpSin {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpSin {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpSin {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 1);
-            match x[0].0 {
-                ValType::F(v0) => ValType::F(v0.sin()),
-                ValType::D(v0) => ValType::D(v0.sin()),
-                ValType::I(v0) => ValType::F((v0 as f32).sin()),
-                ValType::L(v0) => ValType::F((v0 as f32).sin()),
-            }
+            x[0].0.sin()
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=sin(x)
+            //y'=cos(x)*x'
             assert_eq!(args.len(), 1);
-            VWrap::new_with_input(OpCos::new(), vec![args[0].clone()])
+            Mul(
+                VWrap::new_with_input(OpCos::new(), vec![args[0].clone()]),
+                args[0].fwd(),
+            )
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 1);
                 let a = VWrap::new_with_input(OpCos::new(), vec![inputs[0].clone()]);
                 vec![Mul(a, out_adj.clone())]
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![inputs[0].cos().mul(out_adj)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        inputs[0].cos().mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let x = &inputs[0];
+        Some(taylor::sin_cos(x, output, &x[0].cos(), false).0)
+    }
 }
 
-impl FWrap for OpCos {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpCos {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpCos {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 1);
-            match x[0].0 {
-                ValType::F(v0) => ValType::F(v0.cos()),
-                ValType::D(v0) => ValType::D(v0.cos()),
-                ValType::I(v0) => ValType::F((v0 as f32).cos()),
-                ValType::L(v0) => ValType::F((v0 as f32).cos()),
-            }
+            x[0].0.cos()
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=cos(x)
+            //y'=-sin(x)*x'
             assert_eq!(args.len(), 1);
             Mul(
-                VWrap::new_with_val(OpConst::new(), ValType::F(-1.)),
-                VWrap::new_with_input(OpSin::new(), vec![args[0].clone()]),
+                Mul(
+                    VWrap::new_with_val(OpConst::new(), T::from_i32(-1)),
+                    VWrap::new_with_input(OpSin::new(), vec![args[0].clone()]),
+                ),
+                args[0].fwd(),
             )
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 1);
                 let a = Mul(
-                    VWrap::new_with_val(OpConst::new(), ValType::F(-1.)),
+                    VWrap::new_with_val(OpConst::new(), T::from_i32(-1)),
                     VWrap::new_with_input(OpSin::new(), vec![inputs[0].clone()]),
                 );
                 vec![Mul(a, out_adj.clone())]
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![inputs[0].sin().neg().mul(out_adj)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        inputs[0].sin().neg().mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let x = &inputs[0];
+        Some(taylor::sin_cos(x, &x[0].sin(), output, false).1)
+    }
 }
 
-impl FWrap for OpTan {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpTan {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpTan {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 1);
-            match x[0].0 {
-                ValType::F(v0) => ValType::F(v0.tan()),
-                ValType::D(v0) => ValType::D(v0.tan()),
-                ValType::I(v0) => ValType::F((v0 as f32).tan()),
-                ValType::L(v0) => ValType::F((v0 as f32).tan()),
-            }
+            x[0].0.tan()
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
             //y'=1/(cos(x))^2
             assert_eq!(args.len(), 1);
-            let one = VWrap::new_with_val(OpConst::new(), ValType::F(1.));
+            let one = VWrap::new_with_val(OpConst::new(), T::from_i32(1));
             Mul(
                 Div(one, Mul(Cos(args[0].clone()), Cos(args[0].clone()))),
                 args[0].fwd(),
             )
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 1);
 
-                let one = VWrap::new_with_val(OpConst::new(), ValType::F(1.));
+                let one = VWrap::new_with_val(OpConst::new(), T::from_i32(1));
                 let a = Div(one, Mul(Cos(inputs[0].clone()), Cos(inputs[0].clone())));
 
                 vec![Mul(a, out_adj.clone())]
             },
         )
     }
+    fn adjoint_val(&self, _inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        //1/cos(x)^2 = 1+y^2
+        vec![T::from_i32(1).add(&output.mul(output)).mul(out_adj)]
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, tangents: &[T]) -> T {
+        T::from_i32(1).add(&output.mul(output)).mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = (1+y^2) x'
+        Some(taylor::riccati(&inputs[0], output, |y, i| {
+            let sq = taylor::sq_coef(y, i);
+            if i == 0 {
+                T::from_i32(1).add(&sq)
+            } else {
+                sq
+            }
+        }))
+    }
 }
 
-impl FWrap for OpPow {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpPow {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpPow {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 2);
-            let base: f32 = x[0].0.into();
-            let expo: f32 = x[1].0.into();
-            if expo < 1e-15 && expo > -1e-15 {
-                ValType::F(1.)
-            } else {
-                ValType::F(base.powf(expo))
-            }
+            x[0].0.powf(&x[1].0)
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
             //y = x^a = exp(ln(x^a)) = exp(a ln(x))
             //y' = exp(a ln(x))( a'*ln(x) + a/x*x') = x^a *(a'*ln(x)+a/x*x')
 
@@ -762,54 +1249,85 @@ impl FWrap for OpPow {
             )
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 //y = x^a = exp(ln(x^a)) = exp(a ln(x))
                 //y' = exp(a ln(x))( a'*ln(x) + a/x*x')
                 //   = x^(a-1)*a*x' + x^a*ln(x) a'
 
                 assert_eq!(inputs.len(), 2);
 
-                let one = VWrap::new_with_val(OpConst::new(), ValType::F(1.));
+                let one = VWrap::new_with_val(OpConst::new(), T::from_i32(1));
 
                 vec![
-                    Mul(
+                    SumLike(
                         Mul(
-                            Pow(inputs[0].clone(), Minus(inputs[1].clone(), one)),
-                            inputs[1].clone(),
+                            Mul(
+                                Pow(inputs[0].clone(), Minus(inputs[1].clone(), one)),
+                                inputs[1].clone(),
+                            ),
+                            out_adj.clone(),
                         ),
-                        out_adj.clone(),
+                        inputs[0].clone(),
                     ),
-                    Mul(
+                    SumLike(
                         Mul(
-                            Pow(inputs[0].clone(), inputs[1].clone()),
-                            Ln(inputs[0].clone()),
+                            Mul(
+                                Pow(inputs[0].clone(), inputs[1].clone()),
+                                Ln(inputs[0].clone()),
+                            ),
+                            out_adj.clone(),
                         ),
-                        out_adj.clone(),
+                        inputs[1].clone(),
                     ),
                 ]
             },
         )
     }
-}
+    fn adjoint_val(&self, inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        let (x, a) = (&inputs[0], &inputs[1]);
+        vec![
+            x.powf(&a.sub(&T::from_i32(1)))
+                .mul(a)
+                .mul(out_adj)
+                .sum_to(&x.shape()),
+            output.mul(&x.ln()).mul(out_adj).sum_to(&a.shape()),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], output: &T, tangents: &[T]) -> T {
+        let (x, a) = (&inputs[0], &inputs[1]);
+        output.mul(&tangents[1].mul(&x.ln()).add(&a.div(x).mul(&tangents[0])))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let (a, b) = (&inputs[0], &inputs[1]);
+        if b[1..].iter().all(|c| c.is_zero()) {
+            Some(taylor::powc(a, &b[0], output))
+        } else {
+            //y = exp(b ln(a))
+            let l = taylor::ln(a, &a[0].ln());
+            Some(taylor::exp(&taylor::mul(b, &l), output))
+        }
+    }
+}
 
-impl FWrap for OpExp {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpExp {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpExp {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 1);
-            let expo: f32 = x[0].0.into();
-            ValType::F(expo.exp())
+            x[0].0.exp()
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
             //y=exp(x)
             //y'=exp(x)*x'
 
@@ -818,73 +1336,92 @@ impl FWrap for OpExp {
             Mul(Exp(args[0].clone()), args[0].fwd())
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 1);
 
                 vec![Mul(Exp(inputs[0].clone()), out_adj.clone())]
             },
         )
     }
+    fn adjoint_val(&self, _inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        vec![output.mul(out_adj)]
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, tangents: &[T]) -> T {
+        output.mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::exp(&inputs[0], output))
+    }
 }
 
-impl FWrap for OpLn {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpLn {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpLn {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 1);
-            let expo: f32 = x[0].0.into();
-            ValType::F(expo.ln())
+            x[0].0.ln()
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
             //y=ln(x)
             //y'= 1/x *x'
 
             assert_eq!(args.len(), 1);
 
-            let one = VWrap::new_with_val(OpConst::new(), ValType::F(1.));
+            let one = VWrap::new_with_val(OpConst::new(), T::from_i32(1));
 
             Mul(Div(one, args[0].clone()), args[0].fwd())
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 1);
 
-                let one = VWrap::new_with_val(OpConst::new(), ValType::F(1.));
+                let one = VWrap::new_with_val(OpConst::new(), T::from_i32(1));
 
                 vec![Mul(Div(one, inputs[0].clone()), out_adj.clone())]
             },
         )
     }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.div(&inputs[0])]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].div(&inputs[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::ln(&inputs[0], output))
+    }
 }
 
-impl FWrap for OpDiv {
-    fn new() -> Box<dyn FWrap>
+impl<T: Value> FWrap<T> for OpDiv {
+    fn new() -> Box<dyn FWrap<T>>
     where
         Self: Sized,
     {
         Box::new(OpDiv {})
     }
-    fn f(&self) -> Box<dyn FnMut(Vec<(ValType, bool)>, Option<ValType>) -> ValType> {
-        Box::new(move |x: Vec<(ValType, bool)>, _v: Option<ValType>| {
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
             assert!(x.len() == 2);
-            let a: f32 = x[0].0.into();
-            let b: f32 = x[1].0.into();
-            ValType::F(a / b)
+            x[0].0.div(&x[1].0)
         })
     }
-    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, &PtrVWrap) -> PtrVWrap> {
-        Box::new(move |args: Vec<PtrVWrap>, _self_ptr: &PtrVWrap| {
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
             //y=a/b
             //y'= (a'b-ab')/(b*b)
 
@@ -899,106 +1436,1991 @@ impl FWrap for OpDiv {
             )
         })
     }
-    fn adjoint(&self) -> Box<dyn FnMut(Vec<PtrVWrap>, PtrVWrap, &PtrVWrap) -> Vec<PtrVWrap>> {
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
         Box::new(
             //y=a/b
             //y'= (a'b-ab')/(b*b) = a'/b - ab'/(b*b)
-            move |inputs: Vec<PtrVWrap>, out_adj: PtrVWrap, _cur: &PtrVWrap| {
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+
+                let one = VWrap::new_with_val(OpConst::new(), T::from_i32(1));
+                let minus_one = VWrap::new_with_val(OpConst::new(), T::from_i32(-1));
+
+                vec![
+                    SumLike(
+                        Mul(Div(one, inputs[1].clone()), out_adj.clone()),
+                        inputs[0].clone(),
+                    ),
+                    SumLike(
+                        Mul(
+                            Div(
+                                Mul(minus_one, inputs[0].clone()),
+                                Mul(inputs[1].clone(), inputs[1].clone()),
+                            ),
+                            out_adj.clone(),
+                        ),
+                        inputs[1].clone(),
+                    ),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        //y=a/b, dy/da=1/b, dy/db=-y/b
+        vec![
+            out_adj.div(&inputs[1]).sum_to(&inputs[0].shape()),
+            output
+                .mul(out_adj)
+                .div(&inputs[1])
+                .neg()
+                .sum_to(&inputs[1].shape()),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], output: &T, tangents: &[T]) -> T {
+        //(a/b)' = (a' - y b')/b
+        tangents[0].sub(&output.mul(&tangents[1])).div(&inputs[1])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        Some(taylor::div(&inputs[0], &inputs[1]))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpMatMul {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpMatMul {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.matmul(&x[1].0)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //(AB)' = A'B + AB'
+
+            assert_eq!(args.len(), 2);
+
+            Add(
+                MatMul(args[0].fwd(), args[1].clone()),
+                MatMul(args[0].clone(), args[1].fwd()),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            //Y=AB
+            //adj(A) = adj(Y) B^T, adj(B) = A^T adj(Y)
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+                vec![
+                    MatMul(out_adj.clone(), Transpose(inputs[1].clone())),
+                    MatMul(Transpose(inputs[0].clone()), out_adj),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![
+            out_adj.matmul(&inputs[1].transpose()),
+            inputs[0].transpose().matmul(out_adj),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0]
+            .matmul(&inputs[1])
+            .add(&inputs[0].matmul(&tangents[1]))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        Some(taylor::convolve(&inputs[0], &inputs[1], |a, b| a.matmul(b)))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpTranspose {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpTranspose {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.transpose()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            Transpose(args[0].fwd())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![Transpose(out_adj)]
+            },
+        )
+    }
+    fn adjoint_val(&self, _inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.transpose()]
+    }
+    fn tangent_val(&self, _inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].transpose()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSum {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSum { axis: None })
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        let axis = self.axis;
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.sum(axis)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        let axis = self.axis;
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            VWrap::new_with_input(Box::new(OpSum { axis }), vec![args[0].fwd()])
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        let axis = self.axis;
+        Box::new(
+            //every summed element receives the adjoint of the sum
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let a = match axis {
+                    Some(ax) => InsertAxis(out_adj, ax),
+                    None => out_adj,
+                };
+                vec![BroadcastLike(a, inputs[0].clone())]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let a = match self.axis {
+            Some(ax) => out_adj.insert_axis(ax),
+            None => out_adj.clone(),
+        };
+        vec![a.broadcast_to(&inputs[0].shape())]
+    }
+    fn tangent_val(&self, _inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].sum(self.axis)
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpMean {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpMean { axis: None })
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        let axis = self.axis;
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            let n = T::from_i32(x[0].0.count(axis) as i32);
+            x[0].0.sum(axis).div(&n)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        let axis = self.axis;
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            VWrap::new_with_input(Box::new(OpMean { axis }), vec![args[0].fwd()])
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        let axis = self.axis;
+        Box::new(
+            //every averaged element receives 1/n of the adjoint of the mean
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let a = match axis {
+                    Some(ax) => InsertAxis(out_adj, ax),
+                    None => out_adj,
+                };
+                let n = VWrap::new_with_input(Box::new(OpCount { axis }), vec![inputs[0].clone()]);
+                vec![Div(BroadcastLike(a, inputs[0].clone()), n)]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let a = match self.axis {
+            Some(ax) => out_adj.insert_axis(ax),
+            None => out_adj.clone(),
+        };
+        let n = T::from_i32(inputs[0].count(self.axis) as i32);
+        vec![a.broadcast_to(&inputs[0].shape()).div(&n)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let n = T::from_i32(inputs[0].count(self.axis) as i32);
+        tangents[0].sum(self.axis).div(&n)
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpCount {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpCount { axis: None })
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        let axis = self.axis;
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            T::from_i32(x[0].0.count(axis) as i32)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |_args: Vec<PtrVWrap<T>>, self_ptr: &PtrVWrap<T>| ZerosLike(self_ptr.clone()))
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![ZerosLike(inputs[0].clone())]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, _out_adj: &T) -> Vec<T> {
+        inputs.iter().map(|i| i.zeros_like()).collect()
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, _tangents: &[T]) -> T {
+        output.zeros_like()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::constant(output, inputs[0].len()))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpReshape {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpReshape { shape: vec![] })
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        let shape = self.shape.clone();
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.reshape(&shape)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        let shape = self.shape.clone();
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            Reshape(args[0].fwd(), &shape)
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![ReshapeLike(out_adj, inputs[0].clone())]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.reshape(&inputs[0].shape())]
+    }
+    fn tangent_val(&self, _inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].reshape(&self.shape)
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpReshapeLike {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpReshapeLike {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.reshape(&x[1].0.shape())
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 2);
+            ReshapeLike(args[0].fwd(), args[1].clone())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+                vec![
+                    ReshapeLike(out_adj, inputs[0].clone()),
+                    ZerosLike(inputs[1].clone()),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.reshape(&inputs[0].shape()), inputs[1].zeros_like()]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].reshape(&inputs[1].shape())
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpBroadcastLike {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpBroadcastLike {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.broadcast_to(&x[1].0.shape())
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 2);
+            BroadcastLike(args[0].fwd(), args[1].clone())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+                vec![
+                    SumLike(out_adj, inputs[0].clone()),
+                    ZerosLike(inputs[1].clone()),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.sum_to(&inputs[0].shape()), inputs[1].zeros_like()]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].broadcast_to(&inputs[1].shape())
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSumLike {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSumLike {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.sum_to(&x[1].0.shape())
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 2);
+            SumLike(args[0].fwd(), args[1].clone())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
                 assert_eq!(inputs.len(), 2);
+                vec![
+                    BroadcastLike(out_adj, inputs[0].clone()),
+                    ZerosLike(inputs[1].clone()),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![
+            out_adj.broadcast_to(&inputs[0].shape()),
+            inputs[1].zeros_like(),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].sum_to(&inputs[1].shape())
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpInsertAxis {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpInsertAxis { axis: 0 })
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        let axis = self.axis;
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.insert_axis(axis)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        let axis = self.axis;
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            InsertAxis(args[0].fwd(), axis)
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        let axis = self.axis;
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![SumAxis(out_adj, axis)]
+            },
+        )
+    }
+    fn adjoint_val(&self, _inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.sum(Some(self.axis))]
+    }
+    fn tangent_val(&self, _inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].insert_axis(self.axis)
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::linear(self.f(), inputs, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSqrt {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSqrt {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.sqrt()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=sqrt(x)
+            //y'=1/(2*sqrt(x))*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Div(Const(T::from_i32(1)), Mul(Const(T::from_i32(2)), Sqrt(x))),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Div(Const(T::from_i32(1)), Mul(Const(T::from_i32(2)), Sqrt(x))),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, _inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.div(&output.mul(&T::from_i32(2)))]
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, tangents: &[T]) -> T {
+        tangents[0].div(&output.mul(&T::from_i32(2)))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::sqrt(&inputs[0], output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpAbs {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpAbs {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.abs()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=|x|
+            //y'=sign(x)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(Sign(x), args[0].fwd())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(Sign(x), out_adj)]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![inputs[0].signum().mul(out_adj)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        inputs[0].signum().mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let x = &inputs[0];
+        let mut s = taylor::scale(x, &x[0].signum());
+        s[0] = output.clone();
+        Some(s)
+    }
+}
+
+impl<T: Value> FWrap<T> for OpTanh {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpTanh {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.tanh()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=tanh(x)
+            //y'=(1-tanh(x)^2)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Sub(Const(T::from_i32(1)), Mul(Tanh(x.clone()), Tanh(x))),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Sub(Const(T::from_i32(1)), Mul(Tanh(x.clone()), Tanh(x))),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, _inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        vec![T::from_i32(1).sub(&output.mul(output)).mul(out_adj)]
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, tangents: &[T]) -> T {
+        T::from_i32(1).sub(&output.mul(output)).mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = (1-y^2) x'
+        Some(taylor::riccati(&inputs[0], output, |y, i| {
+            let sq = taylor::sq_coef(y, i);
+            if i == 0 {
+                T::from_i32(1).sub(&sq)
+            } else {
+                sq.neg()
+            }
+        }))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSinh {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSinh {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.sinh()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=sinh(x)
+            //y'=cosh(x)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(Cosh(x), args[0].fwd())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(Cosh(x), out_adj)]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![inputs[0].cosh().mul(out_adj)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        inputs[0].cosh().mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let x = &inputs[0];
+        Some(taylor::sin_cos(x, output, &x[0].cosh(), true).0)
+    }
+}
+
+impl<T: Value> FWrap<T> for OpCosh {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpCosh {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.cosh()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=cosh(x)
+            //y'=sinh(x)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(Sinh(x), args[0].fwd())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(Sinh(x), out_adj)]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![inputs[0].sinh().mul(out_adj)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        inputs[0].sinh().mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let x = &inputs[0];
+        Some(taylor::sin_cos(x, &x[0].sinh(), output, true).1)
+    }
+}
+
+impl<T: Value> FWrap<T> for OpAsin {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpAsin {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.asin()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=asin(x)
+            //y'=1/sqrt(1-x^2)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Div(
+                    Const(T::from_i32(1)),
+                    Sqrt(Sub(Const(T::from_i32(1)), Mul(x.clone(), x))),
+                ),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Div(
+                        Const(T::from_i32(1)),
+                        Sqrt(Sub(Const(T::from_i32(1)), Mul(x.clone(), x))),
+                    ),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let x = &inputs[0];
+        vec![out_adj.div(&T::from_i32(1).sub(&x.mul(x)).sqrt())]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let x = &inputs[0];
+        tangents[0].div(&T::from_i32(1).sub(&x.mul(x)).sqrt())
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = x'/sqrt(1-x^2)
+        let x = &inputs[0];
+        let r = taylor::one_minus_sq_sqrt(&x[..x.len() - 1]);
+        Some(taylor::integrate(
+            output,
+            &taylor::div(&taylor::deriv(x), &r),
+        ))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpAcos {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpAcos {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.acos()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=acos(x)
+            //y'=-1/sqrt(1-x^2)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Div(
+                    Const(T::from_i32(-1)),
+                    Sqrt(Sub(Const(T::from_i32(1)), Mul(x.clone(), x))),
+                ),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Div(
+                        Const(T::from_i32(-1)),
+                        Sqrt(Sub(Const(T::from_i32(1)), Mul(x.clone(), x))),
+                    ),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let x = &inputs[0];
+        vec![out_adj.div(&T::from_i32(1).sub(&x.mul(x)).sqrt()).neg()]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let x = &inputs[0];
+        tangents[0].div(&T::from_i32(1).sub(&x.mul(x)).sqrt()).neg()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = -x'/sqrt(1-x^2)
+        let x = &inputs[0];
+        let r = taylor::one_minus_sq_sqrt(&x[..x.len() - 1]);
+        let d = taylor::neg(&taylor::div(&taylor::deriv(x), &r));
+        Some(taylor::integrate(output, &d))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpAtan {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpAtan {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.atan()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=atan(x)
+            //y'=1/(1+x^2)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Div(
+                    Const(T::from_i32(1)),
+                    Add(Const(T::from_i32(1)), Mul(x.clone(), x)),
+                ),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Div(
+                        Const(T::from_i32(1)),
+                        Add(Const(T::from_i32(1)), Mul(x.clone(), x)),
+                    ),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let x = &inputs[0];
+        vec![out_adj.div(&T::from_i32(1).add(&x.mul(x)))]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let x = &inputs[0];
+        tangents[0].div(&T::from_i32(1).add(&x.mul(x)))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = x'/(1+x^2)
+        let x = &inputs[0];
+        let xs = &x[..x.len() - 1];
+        let mut r = taylor::mul(xs, xs);
+        r[0] = T::from_i32(1).add(&r[0]);
+        Some(taylor::integrate(
+            output,
+            &taylor::div(&taylor::deriv(x), &r),
+        ))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpLog10 {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpLog10 {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.log10()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=log10(x)
+            //y'=1/(x*ln(10))*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Div(
+                    Const(T::from_i32(1)),
+                    Mul(x.clone(), ScalarLike(x, std::f64::consts::LN_10)),
+                ),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Div(
+                        Const(T::from_i32(1)),
+                        Mul(x.clone(), ScalarLike(x, std::f64::consts::LN_10)),
+                    ),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let x = &inputs[0];
+        vec![out_adj.div(&x.mul(&x.scalar_like(std::f64::consts::LN_10)))]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let x = &inputs[0];
+        tangents[0].div(&x.mul(&x.scalar_like(std::f64::consts::LN_10)))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let x = &inputs[0];
+        let mut s = taylor::ln(x, output);
+        let c = x[0].scalar_like(std::f64::consts::LN_10);
+        for s in s.iter_mut().skip(1) {
+            *s = s.div(&c);
+        }
+        Some(s)
+    }
+}
+
+impl<T: Value> FWrap<T> for OpLog2 {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpLog2 {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.log2()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=log2(x)
+            //y'=1/(x*ln(2))*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Div(
+                    Const(T::from_i32(1)),
+                    Mul(x.clone(), ScalarLike(x, std::f64::consts::LN_2)),
+                ),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Div(
+                        Const(T::from_i32(1)),
+                        Mul(x.clone(), ScalarLike(x, std::f64::consts::LN_2)),
+                    ),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let x = &inputs[0];
+        vec![out_adj.div(&x.mul(&x.scalar_like(std::f64::consts::LN_2)))]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let x = &inputs[0];
+        tangents[0].div(&x.mul(&x.scalar_like(std::f64::consts::LN_2)))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let x = &inputs[0];
+        let mut s = taylor::ln(x, output);
+        let c = x[0].scalar_like(std::f64::consts::LN_2);
+        for s in s.iter_mut().skip(1) {
+            *s = s.div(&c);
+        }
+        Some(s)
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSigmoid {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSigmoid {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.sigmoid()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=1/(1+exp(-x))
+            //y'=y*(1-y)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Mul(Sigmoid(x.clone()), Sub(Const(T::from_i32(1)), Sigmoid(x))),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Mul(Sigmoid(x.clone()), Sub(Const(T::from_i32(1)), Sigmoid(x))),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, _inputs: &[T], output: &T, out_adj: &T) -> Vec<T> {
+        vec![output.mul(&T::from_i32(1).sub(output)).mul(out_adj)]
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, tangents: &[T]) -> T {
+        output.mul(&T::from_i32(1).sub(output)).mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::sigmoid(&inputs[0], output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSoftplus {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSoftplus {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.softplus()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=ln(1+exp(x))
+            //y'=sigmoid(x)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(Sigmoid(x), args[0].fwd())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(Sigmoid(x), out_adj)]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![inputs[0].sigmoid().mul(out_adj)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        inputs[0].sigmoid().mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = sigmoid(x) x'
+        let x = &inputs[0];
+        let s = taylor::sigmoid(&x[..x.len() - 1], &x[0].sigmoid());
+        Some(taylor::integrate(
+            output,
+            &taylor::mul(&s, &taylor::deriv(x)),
+        ))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpErf {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpErf {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.erf()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=erf(x)
+            //y'=2/sqrt(pi)*exp(-x^2)*x'
+            assert_eq!(args.len(), 1);
+            let x = args[0].clone();
+            Mul(
+                Mul(
+                    ScalarLike(x.clone(), std::f64::consts::FRAC_2_SQRT_PI),
+                    Exp(Neg(Mul(x.clone(), x))),
+                ),
+                args[0].fwd(),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                let x = inputs[0].clone();
+                vec![Mul(
+                    Mul(
+                        ScalarLike(x.clone(), std::f64::consts::FRAC_2_SQRT_PI),
+                        Exp(Neg(Mul(x.clone(), x))),
+                    ),
+                    out_adj,
+                )]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let x = &inputs[0];
+        let d = x
+            .scalar_like(std::f64::consts::FRAC_2_SQRT_PI)
+            .mul(&x.mul(x).neg().exp());
+        vec![d.mul(out_adj)]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let x = &inputs[0];
+        let d = x
+            .scalar_like(std::f64::consts::FRAC_2_SQRT_PI)
+            .mul(&x.mul(x).neg().exp());
+        d.mul(&tangents[0])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = 2/sqrt(pi) exp(-x^2) x'
+        let x = &inputs[0];
+        let xs = &x[..x.len() - 1];
+        let u = taylor::neg(&taylor::mul(xs, xs));
+        let e = taylor::exp(&u, &u[0].exp());
+        let e = taylor::scale(&e, &x[0].scalar_like(std::f64::consts::FRAC_2_SQRT_PI));
+        Some(taylor::integrate(
+            output,
+            &taylor::mul(&e, &taylor::deriv(x)),
+        ))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpNeg {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpNeg {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.neg()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            Neg(args[0].fwd())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![Neg(out_adj)]
+            },
+        )
+    }
+    fn adjoint_val(&self, _inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![out_adj.neg()]
+    }
+    fn tangent_val(&self, _inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].neg()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        Some(taylor::neg(&inputs[0]))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSub {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSub {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.sub(&x[1].0)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 2);
+            Sub(args[0].fwd(), args[1].fwd())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+                vec![
+                    SumLike(out_adj.clone(), inputs[0].clone()),
+                    SumLike(Neg(out_adj), inputs[1].clone()),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        vec![
+            out_adj.sum_to(&inputs[0].shape()),
+            out_adj.neg().sum_to(&inputs[1].shape()),
+        ]
+    }
+    fn tangent_val(&self, _inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        tangents[0].sub(&tangents[1])
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        Some(taylor::sub(&inputs[0], &inputs[1]))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpAtan2 {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpAtan2 {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.atan2(&x[1].0)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //z=atan2(y,x)
+            //z'=(x*y'-y*x')/(x^2+y^2)
+            assert_eq!(args.len(), 2);
+            let (y, x) = (args[0].clone(), args[1].clone());
+            Div(
+                Sub(Mul(x.clone(), y.fwd()), Mul(y.clone(), x.fwd())),
+                Add(Mul(x.clone(), x), Mul(y.clone(), y)),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+                let (y, x) = (inputs[0].clone(), inputs[1].clone());
+                let r2 = Add(Mul(x.clone(), x.clone()), Mul(y.clone(), y.clone()));
+                vec![
+                    SumLike(Mul(Div(x.clone(), r2.clone()), out_adj.clone()), y.clone()),
+                    SumLike(Mul(Div(Neg(y), r2), out_adj), x),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let (y, x) = (&inputs[0], &inputs[1]);
+        let r2 = x.mul(x).add(&y.mul(y));
+        vec![
+            x.div(&r2).mul(out_adj).sum_to(&y.shape()),
+            y.neg().div(&r2).mul(out_adj).sum_to(&x.shape()),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let (y, x) = (&inputs[0], &inputs[1]);
+        x.mul(&tangents[0])
+            .sub(&y.mul(&tangents[1]))
+            .div(&x.mul(x).add(&y.mul(y)))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        //y' = (b a' - a b')/(a^2+b^2)
+        let (a, b) = (&inputs[0], &inputs[1]);
+        let n = a.len() - 1;
+        let (a_s, b_s) = (&a[..n], &b[..n]);
+        let num = taylor::sub(
+            &taylor::mul(b_s, &taylor::deriv(a)),
+            &taylor::mul(a_s, &taylor::deriv(b)),
+        );
+        let den = taylor::add(&taylor::mul(a_s, a_s), &taylor::mul(b_s, b_s));
+        Some(taylor::integrate(output, &taylor::div(&num, &den)))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpMax {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpMax {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.max(&x[1].0)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=max(a,b)
+            //y'=m*a'+(1-m)*b', m=(1+sign(a-b))/2
+            assert_eq!(args.len(), 2);
+            let m = Div(
+                Add(
+                    Const(T::from_i32(1)),
+                    Sign(Sub(args[0].clone(), args[1].clone())),
+                ),
+                Const(T::from_i32(2)),
+            );
+            Add(
+                Mul(m.clone(), args[0].fwd()),
+                Mul(Sub(Const(T::from_i32(1)), m), args[1].fwd()),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+                let m = Div(
+                    Add(
+                        Const(T::from_i32(1)),
+                        Sign(Sub(inputs[0].clone(), inputs[1].clone())),
+                    ),
+                    Const(T::from_i32(2)),
+                );
+                vec![
+                    SumLike(Mul(m.clone(), out_adj.clone()), inputs[0].clone()),
+                    SumLike(
+                        Mul(Sub(Const(T::from_i32(1)), m), out_adj),
+                        inputs[1].clone(),
+                    ),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let (a, b) = (&inputs[0], &inputs[1]);
+        let m = T::from_i32(1).add(&a.sub(b).signum()).div(&T::from_i32(2));
+        vec![
+            m.mul(out_adj).sum_to(&a.shape()),
+            T::from_i32(1).sub(&m).mul(out_adj).sum_to(&b.shape()),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let m = T::from_i32(1)
+            .add(&inputs[0].sub(&inputs[1]).signum())
+            .div(&T::from_i32(2));
+        m.mul(&tangents[0])
+            .add(&T::from_i32(1).sub(&m).mul(&tangents[1]))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let (a, b) = (&inputs[0], &inputs[1]);
+        let m = T::from_i32(1)
+            .add(&a[0].sub(&b[0]).signum())
+            .div(&T::from_i32(2));
+        Some(taylor::select(a, b, &m, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpMin {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpMin {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 2);
+            x[0].0.min(&x[1].0)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            //y=min(a,b)
+            //y'=m*a'+(1-m)*b', m=(1-sign(a-b))/2
+            assert_eq!(args.len(), 2);
+            let m = Div(
+                Sub(
+                    Const(T::from_i32(1)),
+                    Sign(Sub(args[0].clone(), args[1].clone())),
+                ),
+                Const(T::from_i32(2)),
+            );
+            Add(
+                Mul(m.clone(), args[0].fwd()),
+                Mul(Sub(Const(T::from_i32(1)), m), args[1].fwd()),
+            )
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 2);
+                let m = Div(
+                    Sub(
+                        Const(T::from_i32(1)),
+                        Sign(Sub(inputs[0].clone(), inputs[1].clone())),
+                    ),
+                    Const(T::from_i32(2)),
+                );
+                vec![
+                    SumLike(Mul(m.clone(), out_adj.clone()), inputs[0].clone()),
+                    SumLike(
+                        Mul(Sub(Const(T::from_i32(1)), m), out_adj),
+                        inputs[1].clone(),
+                    ),
+                ]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, out_adj: &T) -> Vec<T> {
+        let (a, b) = (&inputs[0], &inputs[1]);
+        let m = T::from_i32(1).sub(&a.sub(b).signum()).div(&T::from_i32(2));
+        vec![
+            m.mul(out_adj).sum_to(&a.shape()),
+            T::from_i32(1).sub(&m).mul(out_adj).sum_to(&b.shape()),
+        ]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, tangents: &[T]) -> T {
+        let m = T::from_i32(1)
+            .sub(&inputs[0].sub(&inputs[1]).signum())
+            .div(&T::from_i32(2));
+        m.mul(&tangents[0])
+            .add(&T::from_i32(1).sub(&m).mul(&tangents[1]))
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        let (a, b) = (&inputs[0], &inputs[1]);
+        let m = T::from_i32(1)
+            .sub(&a[0].sub(&b[0]).signum())
+            .div(&T::from_i32(2));
+        Some(taylor::select(a, b, &m, output))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpSign {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpSign {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.signum()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            ZerosLike(args[0].clone())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![ZerosLike(inputs[0].clone())]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, _out_adj: &T) -> Vec<T> {
+        inputs.iter().map(|i| i.zeros_like()).collect()
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, _tangents: &[T]) -> T {
+        output.zeros_like()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::constant(output, inputs[0].len()))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpStopGradient {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpStopGradient {})
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.clone()
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            assert_eq!(args.len(), 1);
+            ZerosLike(args[0].clone())
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![ZerosLike(inputs[0].clone())]
+            },
+        )
+    }
+    fn stops_gradient(&self, _k: usize) -> bool {
+        true
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, _out_adj: &T) -> Vec<T> {
+        vec![inputs[0].zeros_like()]
+    }
+    fn tangent_val(&self, inputs: &[T], _output: &T, _tangents: &[T]) -> T {
+        inputs[0].zeros_like()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::constant(output, inputs[0].len()))
+    }
+}
+
+impl<T: Value> FWrap<T> for OpScalarLike {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        Box::new(OpScalarLike { v: 0. })
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        let v = self.v;
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            assert!(x.len() == 1);
+            x[0].0.scalar_like(v)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        Box::new(move |_args: Vec<PtrVWrap<T>>, self_ptr: &PtrVWrap<T>| ZerosLike(self_ptr.clone()))
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, _out_adj: PtrVWrap<T>, _cur: &PtrVWrap<T>| {
+                assert_eq!(inputs.len(), 1);
+                vec![ZerosLike(inputs[0].clone())]
+            },
+        )
+    }
+    fn adjoint_val(&self, inputs: &[T], _output: &T, _out_adj: &T) -> Vec<T> {
+        inputs.iter().map(|i| i.zeros_like()).collect()
+    }
+    fn tangent_val(&self, _inputs: &[T], output: &T, _tangents: &[T]) -> T {
+        output.zeros_like()
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        Some(taylor::constant(output, inputs[0].len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn Mul<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpMul::new());
+    a.set_inp(vec![arg0, arg1]);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Add<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpAdd::new());
+    a.set_inp(vec![arg0, arg1]);
+    a
+}
+
+/// same as Sub
+#[allow(dead_code)]
+pub fn Minus<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    Sub(arg0, arg1)
+}
+
+/// constant value, derivatives with respect to it are zero
+#[allow(dead_code)]
+pub fn Const<T: Value>(arg0: T) -> PtrVWrap<T> {
+    VWrap::new_with_val(OpConst::new(), arg0)
+}
+
+/// 1 with the shape of the value of arg0
+#[allow(dead_code)]
+fn OnesLike<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpOne::new(), vec![arg0])
+}
+
+/// 0 with the shape of the value of arg0
+#[allow(dead_code)]
+pub(crate) fn ZerosLike<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpZero::new(), vec![arg0])
+}
+
+#[allow(dead_code)]
+pub fn Leaf<T: Value>(arg0: T) -> PtrVWrap<T> {
+    let a = VWrap::new_with_val(OpLeaf::new(), arg0);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Sin<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpSin::new());
+    a.set_inp(vec![arg0]);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Cos<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpCos::new());
+    a.set_inp(vec![arg0]);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Tan<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpTan::new());
+    a.set_inp(vec![arg0]);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Exp<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpExp::new());
+    a.set_inp(vec![arg0]);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Ln<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpLn::new());
+    a.set_inp(vec![arg0]);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Div<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpDiv::new());
+    a.set_inp(vec![arg0, arg1]);
+    a
+}
+
+#[allow(dead_code)]
+pub fn Pow<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    let mut a = VWrap::new(OpPow::new());
+    a.set_inp(vec![arg0, arg1]);
+    a
+}
+
+/// matrix product of 2-dimensional tensors, or product of two scalars
+///
+/// vectors are not promoted, reshape them first: W x for a linear layer is
+/// MatMul(w, Reshape(x, &[n, 1])) and x W is MatMul(Reshape(x, &[1, n]), w).
+/// Other operands are rejected here when the inputs already hold values,
+/// otherwise on evaluation
+#[allow(dead_code)]
+pub fn MatMul<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    let shapes = (known_shape(&arg0), known_shape(&arg1));
+    let ok = match &shapes {
+        (Some(a), Some(b)) => (a.len() == 2 && b.len() == 2) || (a.is_empty() && b.is_empty()),
+        (Some(s), None) | (None, Some(s)) => s.len() == 2 || s.is_empty(),
+        (None, None) => true,
+    };
+    assert!(
+        ok,
+        "matmul expects two 2-dimensional tensors or two scalars, got shapes {:?} and {:?}, \
+         reshape vectors to a column or a row",
+        shapes.0, shapes.1
+    );
+    VWrap::new_with_input(OpMatMul::new(), vec![arg0, arg1])
+}
+
+/// shape of the value of a node if it is known without evaluation
+fn known_shape<T: Value>(n: &PtrVWrap<T>) -> Option<Vec<usize>> {
+    let n = n.0.deref().borrow();
+    if n.inp.is_empty() || !n.dirty {
+        n.val.as_ref().map(|v| v.shape())
+    } else {
+        None
+    }
+}
+
+/// reverses the order of axes, matrix transpose for 2-dimensional tensors
+#[allow(dead_code)]
+pub fn Transpose<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpTranspose::new(), vec![arg0])
+}
+
+/// sum over all elements
+#[allow(dead_code)]
+pub fn Sum<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(Box::new(OpSum { axis: None }), vec![arg0])
+}
+
+/// sum over the given axis, the axis is removed
+#[allow(dead_code)]
+pub fn SumAxis<T: Value>(arg0: PtrVWrap<T>, axis: usize) -> PtrVWrap<T> {
+    VWrap::new_with_input(Box::new(OpSum { axis: Some(axis) }), vec![arg0])
+}
+
+/// mean over all elements
+#[allow(dead_code)]
+pub fn Mean<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(Box::new(OpMean { axis: None }), vec![arg0])
+}
+
+/// mean over the given axis, the axis is removed
+#[allow(dead_code)]
+pub fn MeanAxis<T: Value>(arg0: PtrVWrap<T>, axis: usize) -> PtrVWrap<T> {
+    VWrap::new_with_input(Box::new(OpMean { axis: Some(axis) }), vec![arg0])
+}
+
+#[allow(dead_code)]
+pub fn Reshape<T: Value>(arg0: PtrVWrap<T>, shape: &[usize]) -> PtrVWrap<T> {
+    VWrap::new_with_input(
+        Box::new(OpReshape {
+            shape: shape.to_vec(),
+        }),
+        vec![arg0],
+    )
+}
+
+#[allow(dead_code)]
+fn ReshapeLike<T: Value>(arg0: PtrVWrap<T>, like: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpReshapeLike::new(), vec![arg0, like])
+}
+
+#[allow(dead_code)]
+fn BroadcastLike<T: Value>(arg0: PtrVWrap<T>, like: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpBroadcastLike::new(), vec![arg0, like])
+}
+
+#[allow(dead_code)]
+fn SumLike<T: Value>(arg0: PtrVWrap<T>, like: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpSumLike::new(), vec![arg0, like])
+}
+
+#[allow(dead_code)]
+fn InsertAxis<T: Value>(arg0: PtrVWrap<T>, axis: usize) -> PtrVWrap<T> {
+    VWrap::new_with_input(Box::new(OpInsertAxis { axis }), vec![arg0])
+}
+
+/// square root
+#[allow(dead_code)]
+pub fn Sqrt<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpSqrt::new(), vec![arg0])
+}
+
+/// absolute value, the derivative at 0 is taken as 0
+#[allow(dead_code)]
+pub fn Abs<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpAbs::new(), vec![arg0])
+}
+
+/// hyperbolic tangent
+#[allow(dead_code)]
+pub fn Tanh<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpTanh::new(), vec![arg0])
+}
+
+/// hyperbolic sine
+#[allow(dead_code)]
+pub fn Sinh<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpSinh::new(), vec![arg0])
+}
+
+/// hyperbolic cosine
+#[allow(dead_code)]
+pub fn Cosh<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpCosh::new(), vec![arg0])
+}
+
+/// inverse sine
+#[allow(dead_code)]
+pub fn Asin<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpAsin::new(), vec![arg0])
+}
+
+/// inverse cosine
+#[allow(dead_code)]
+pub fn Acos<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpAcos::new(), vec![arg0])
+}
+
+/// inverse tangent
+#[allow(dead_code)]
+pub fn Atan<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpAtan::new(), vec![arg0])
+}
 
-                let one = VWrap::new_with_val(OpConst::new(), ValType::F(1.));
-                let minus_one = VWrap::new_with_val(OpConst::new(), ValType::F(-1.));
+/// base 10 logarithm
+#[allow(dead_code)]
+pub fn Log10<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpLog10::new(), vec![arg0])
+}
 
-                vec![
-                    Mul(Div(one, inputs[1].clone()), out_adj.clone()),
-                    Mul(
-                        Div(
-                            Mul(minus_one, inputs[0].clone()),
-                            Mul(inputs[1].clone(), inputs[1].clone()),
-                        ),
-                        out_adj.clone(),
-                    ),
-                ]
-            },
-        )
-    }
+/// base 2 logarithm
+#[allow(dead_code)]
+pub fn Log2<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpLog2::new(), vec![arg0])
 }
 
+/// logistic function 1/(1+exp(-x))
 #[allow(dead_code)]
-pub fn Mul(arg0: PtrVWrap, arg1: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpMul::new());
-    a.set_inp(vec![arg0, arg1]);
-    a
+pub fn Sigmoid<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpSigmoid::new(), vec![arg0])
 }
 
+/// ln(1+exp(x))
 #[allow(dead_code)]
-pub fn Add(arg0: PtrVWrap, arg1: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpAdd::new());
-    a.set_inp(vec![arg0, arg1]);
-    a
+pub fn Softplus<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpSoftplus::new(), vec![arg0])
 }
 
+/// error function
 #[allow(dead_code)]
-pub fn Minus(arg0: PtrVWrap, arg1: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpAdd::new());
-    let temp = VWrap::new_with_val(OpConst::new(), ValType::F(-1.));
-    a.set_inp(vec![arg0, Mul(arg1, temp)]);
-    a
+pub fn Erf<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpErf::new(), vec![arg0])
 }
 
+/// value of the input, treated as a constant by fwd() and rev()
 #[allow(dead_code)]
-pub fn Leaf(arg0: ValType) -> PtrVWrap {
-    let a = VWrap::new_with_val(OpLeaf::new(), arg0);
-    a
+pub fn StopGradient<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpStopGradient::new(), vec![arg0])
 }
 
 #[allow(dead_code)]
-pub fn Sin(arg0: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpSin::new());
-    a.set_inp(vec![arg0]);
-    a
+pub fn Neg<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpNeg::new(), vec![arg0])
 }
 
 #[allow(dead_code)]
-pub fn Cos(arg0: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpCos::new());
-    a.set_inp(vec![arg0]);
-    a
+pub fn Sub<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpSub::new(), vec![arg0, arg1])
 }
 
+/// four quadrant inverse tangent of arg0/arg1
 #[allow(dead_code)]
-pub fn Tan(arg0: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpTan::new());
-    a.set_inp(vec![arg0]);
-    a
+pub fn Atan2<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpAtan2::new(), vec![arg0, arg1])
 }
 
+/// elementwise maximum, ties split the derivative equally between both inputs
 #[allow(dead_code)]
-pub fn Exp(arg0: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpExp::new());
-    a.set_inp(vec![arg0]);
-    a
+pub fn Max<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpMax::new(), vec![arg0, arg1])
 }
 
+/// elementwise minimum, ties split the derivative equally between both inputs
 #[allow(dead_code)]
-pub fn Ln(arg0: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpLn::new());
-    a.set_inp(vec![arg0]);
-    a
+pub fn Min<T: Value>(arg0: PtrVWrap<T>, arg1: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpMin::new(), vec![arg0, arg1])
 }
 
 #[allow(dead_code)]
-pub fn Div(arg0: PtrVWrap, arg1: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpDiv::new());
-    a.set_inp(vec![arg0, arg1]);
-    a
+fn Sign<T: Value>(arg0: PtrVWrap<T>) -> PtrVWrap<T> {
+    VWrap::new_with_input(OpSign::new(), vec![arg0])
 }
 
 #[allow(dead_code)]
-pub fn Pow(arg0: PtrVWrap, arg1: PtrVWrap) -> PtrVWrap {
-    let mut a = VWrap::new(OpPow::new());
-    a.set_inp(vec![arg0, arg1]);
-    a
+fn ScalarLike<T: Value>(arg0: PtrVWrap<T>, v: f64) -> PtrVWrap<T> {
+    VWrap::new_with_input(Box::new(OpScalarLike { v }), vec![arg0])
 }
 
 #[cfg(test)]
@@ -1017,14 +3439,14 @@ fn test_loop_fwd() {
 
     let vl = l.apply_fwd();
 
-    dbg!(vl);
+    dbg!(&vl);
 
     assert!(eq_f32(vl.into(), 2048.));
 
     let mut g = l.fwd();
     let h = g.apply_fwd();
 
-    dbg!(h);
+    dbg!(&h);
 
     assert!(eq_f32(h.into(), 1024.));
 }
@@ -1041,7 +3463,7 @@ fn test_simple_fwd() {
 
     let c = b.apply_fwd();
 
-    dbg!(c);
+    dbg!(&c);
 
     assert!(eq_f32(c.into(), 3.));
 }
@@ -1093,7 +3515,7 @@ fn test_simple_rev() {
         .expect("l0 adjoint missing")
         .apply_rev();
 
-    dbg!(ret);
+    dbg!(&ret);
 
     assert!(eq_f32(ret.into(), 3.));
 }
@@ -1713,3 +4135,788 @@ fn test_pow_rev_2() {
 
     assert!(eq_f32(g.into(), 4f32.ln() * 4f32.powf(3. * 2.) * 3.));
 }
+
+#[cfg(test)]
+fn eq_tensor(a: ValType, b: &[f64]) -> bool {
+    match a {
+        ValType::T(x) => {
+            x.len() == b.len() && x.iter().zip(b.iter()).all(|(p, q)| (p - q).abs() < 0.01)
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn test_tensor_fwd() {
+    //y=3*x^2 elementwise where x=[1,2,3]
+    //y'=6x
+
+    use ndarray::{arr1, ArrayD};
+
+    let x: ArrayD<f64> = arr1(&[1., 2., 3.]).into_dyn();
+    let l0 = Leaf(ValType::T(x)).active();
+    let l1 = Leaf(ValType::F(3.));
+    let mut a = Mul(Mul(l0.clone(), l0.clone()), l1.clone());
+
+    assert!(eq_tensor(a.apply_fwd(), &[3., 12., 27.]));
+
+    assert!(eq_tensor(a.fwd().apply_fwd(), &[6., 12., 18.]));
+
+    assert!(eq_tensor(a.fwd().fwd().apply_fwd(), &[6., 6., 6.]));
+}
+
+#[test]
+fn test_tensor_rev() {
+    //y=3*sin(x) elementwise where x=[1,2,3]
+    //y'=3*cos(x)
+    //y''=-3*sin(x)
+
+    use ndarray::{arr1, ArrayD};
+
+    let x: ArrayD<f64> = arr1(&[1., 2., 3.]).into_dyn();
+    let l0 = Leaf(ValType::T(x));
+    let l1 = Leaf(ValType::T(arr1(&[3., 3., 3.]).into_dyn()));
+    let a = Mul(Sin(l0.clone()), l1.clone());
+
+    let g = a
+        .rev()
+        .get_mut(&l0)
+        .expect("l0 adjoint missing")
+        .apply_rev();
+
+    assert!(eq_tensor(
+        g,
+        &[3. * 1f64.cos(), 3. * 2f64.cos(), 3. * 3f64.cos()]
+    ));
+
+    let gg = a
+        .rev()
+        .get_mut(&l0)
+        .expect("l0 adjoint missing")
+        .rev()
+        .get_mut(&l0)
+        .expect("l0 adjoint missing")
+        .apply_rev();
+
+    assert!(eq_tensor(
+        gg,
+        &[-3. * 1f64.sin(), -3. * 2f64.sin(), -3. * 3f64.sin()]
+    ));
+}
+
+#[test]
+fn test_least_squares() {
+    //loss = mean((Xw-y)^2)
+    //d(loss)/dw = 2/n X^T(Xw-y)
+    //d(sum(d(loss)/dw))/dw = 2/n X^T X 1
+
+    use ndarray::{arr2, Array2};
+
+    let x: Array2<f64> = arr2(&[[1., 2.], [3., 4.], [5., 6.]]);
+    let w: Array2<f64> = arr2(&[[0.5], [-1.]]);
+    let y: Array2<f64> = arr2(&[[1.], [2.], [3.]]);
+
+    let l_x = Leaf(ValType::T(x.clone().into_dyn()));
+    let l_w = Leaf(ValType::T(w.clone().into_dyn())).active();
+    let l_y = Leaf(ValType::T(y.clone().into_dyn()));
+
+    let r = Minus(MatMul(l_x.clone(), l_w.clone()), l_y.clone());
+    let mut loss = Mean(Mul(r.clone(), r.clone()));
+
+    let res = x.dot(&w) - &y;
+    let expect_loss = res.mapv(|v| v * v).sum() / 3.;
+    let expect_grad = x.t().dot(&res) * (2. / 3.);
+    let expect_hess = x.t().dot(&x).sum_axis(ndarray::Axis(1)) * (2. / 3.);
+
+    assert!(eq_f32(loss.apply_fwd().into(), expect_loss as f32));
+
+    let mut g = loss.rev().get_mut(&l_w).expect("w adjoint missing").clone();
+
+    assert!(eq_tensor(
+        g.apply_rev(),
+        &expect_grad.iter().cloned().collect::<Vec<_>>()
+    ));
+
+    assert!(eq_f32(
+        loss.fwd().apply_fwd().into(),
+        expect_grad.sum() as f32
+    ));
+
+    let h = g
+        .rev()
+        .get_mut(&l_w)
+        .expect("w adjoint missing")
+        .apply_rev();
+
+    assert!(eq_tensor(
+        h,
+        &expect_hess.iter().cloned().collect::<Vec<_>>()
+    ));
+}
+
+#[test]
+fn test_matmul_scalars() {
+    //y = a b for scalar a, b behaves as Mul
+    let a = Leaf(ValType::D(3.));
+    let b = Leaf(ValType::D(-2.));
+    let y = MatMul(a.clone(), b.clone());
+    let adj = y.rev();
+    assert!(eq_f32(adj[&a].clone().apply_rev().into(), -2.));
+    assert!(eq_f32(adj[&b].clone().apply_rev().into(), 3.));
+}
+
+#[test]
+#[should_panic(expected = "matmul expects two 2-dimensional tensors or two scalars")]
+fn test_matmul_rejects_vector() {
+    use ndarray::{arr1, arr2};
+
+    let a = Leaf(ValType::T(arr2(&[[1., 2.], [3., 4.]]).into_dyn()));
+    let v = Leaf(ValType::T(arr1(&[1., 2.]).into_dyn()));
+    MatMul(a, v);
+}
+
+#[test]
+#[should_panic(expected = "matmul expects two 2-dimensional tensors or two scalars")]
+fn test_matmul_rejects_scalar_operand() {
+    use ndarray::arr2;
+
+    //shape of the scalar side is only known on evaluation
+    let a = Leaf(ValType::T(arr2(&[[1., 2.], [3., 4.]]).into_dyn()));
+    let s = Exp(Leaf(ValType::D(0.)));
+    MatMul(s, a).apply_fwd();
+}
+
+#[test]
+fn test_matmul_linear_layer() {
+    use crate::grad::{grad, grad_with, Mode};
+    use ndarray::{arr1, arr2};
+
+    //l = sum((W x)^2) with x reshaped to a column
+    //dl/dW = 2 W x x^T, dl/dx = 2 W^T W x
+    let w = Leaf(ValType::T(arr2(&[[1., 2., 0.], [0., -1., 3.]]).into_dyn()));
+    let x = Leaf(ValType::T(arr1(&[1., -1., 2.]).into_dyn()));
+    let y = MatMul(w.clone(), Reshape(x.clone(), &[3, 1]));
+    let l = Sum(Mul(y.clone(), y));
+
+    let g = grad(&l, &[w.clone(), x.clone()]);
+    assert!(eq_tensor(g[0].clone(), &[-2., 2., -4., 14., -14., 28.]));
+    assert_eq!(g[1].shape(), vec![3]);
+    assert!(eq_tensor(g[1].clone(), &[-2., -18., 42.]));
+    let n = grad_with(&l, &[w.clone(), x.clone()], Mode::Numeric);
+    assert!(eq_tensor(n[0].clone(), &[-2., 2., -4., 14., -14., 28.]));
+    assert!(eq_tensor(n[1].clone(), &[-2., -18., 42.]));
+
+    //row vector times matrix, dl/du = 2 u W W^T
+    let u = Leaf(ValType::T(arr1(&[1., 1.]).into_dyn()));
+    let z = MatMul(Reshape(u.clone(), &[1, 2]), w.clone());
+    let l = Sum(Mul(z.clone(), z));
+    assert!(eq_tensor(grad(&l, &[u.clone()])[0].clone(), &[6., 16.]));
+}
+
+#[test]
+fn test_tensor_reductions() {
+    //f1 = sum(reshape(transpose(A), [6]) * c), df1/dA = transpose(reshape(c, [3, 2]))
+    //f2 = sum(mean_axis(A, 0)), df2/dA = 1/2
+    //f3 = mean(sum_axis(A, 1)), df3/dA = 1/2
+
+    use ndarray::{arr1, arr2};
+
+    let a = arr2(&[[1., 2., 3.], [4., 5., 6.]]);
+    let c = arr1(&[1., 2., 3., 4., 5., 6.]);
+
+    let l_a = Leaf(ValType::T(a.into_dyn()));
+    let l_c = Leaf(ValType::T(c.into_dyn()));
+
+    let f1 = Sum(Mul(Reshape(Transpose(l_a.clone()), &[6]), l_c.clone()));
+    let f2 = Sum(MeanAxis(l_a.clone(), 0));
+    let f3 = Mean(SumAxis(l_a.clone(), 1));
+
+    let g1 = f1
+        .rev()
+        .get_mut(&l_a)
+        .expect("A adjoint missing")
+        .apply_rev();
+    assert_eq!(g1.shape(), vec![2, 3]);
+    assert!(eq_tensor(g1, &[1., 3., 5., 2., 4., 6.]));
+
+    let g2 = f2
+        .rev()
+        .get_mut(&l_a)
+        .expect("A adjoint missing")
+        .apply_rev();
+    assert!(eq_tensor(g2, &[0.5; 6]));
+
+    let g3 = f3
+        .rev()
+        .get_mut(&l_a)
+        .expect("A adjoint missing")
+        .apply_rev();
+    assert!(eq_tensor(g3, &[0.5; 6]));
+}
+
+#[test]
+fn test_broadcast() {
+    //Y = X*w + b with X of shape [2,3], w of shape [3], b of shape [1,3]
+    //f = sum(Y / d + Y^p) with scalar d, p
+    //df/dw = sum_rows(X * (1/d + p*Y^(p-1)))
+    //df/db = sum_rows(1/d + p*Y^(p-1))
+    //df/dd = sum(-Y/d^2)
+    //df/dp = sum(Y^p ln(Y))
+
+    use ndarray::{arr1, arr2, Axis};
+
+    let x = arr2(&[[1., 2., 3.], [4., 5., 6.]]);
+    let w = arr1(&[0.5, 1., 1.5]);
+    let b = arr2(&[[1., 1., 2.]]);
+    let (d, p) = (2., 2.);
+
+    let l_x = Leaf(ValType::T(x.clone().into_dyn()));
+    let l_w = Leaf(ValType::T(w.clone().into_dyn()));
+    let l_b = Leaf(ValType::T(b.clone().into_dyn()));
+    let l_d = Leaf(ValType::F(d as f32));
+    let l_p = Leaf(ValType::F(p as f32));
+
+    let y = Add(Mul(l_x.clone(), l_w.clone()), l_b.clone());
+    let f = Sum(Add(
+        Div(y.clone(), l_d.clone()),
+        Pow(y.clone(), l_p.clone()),
+    ));
+
+    let y_val = &x * &w + &b;
+    let dy = y_val.mapv(|v| 1. / d + p * v.powf(p - 1.));
+
+    let mut adjoints = f.rev();
+
+    let g_w = adjoints
+        .get_mut(&l_w)
+        .expect("w adjoint missing")
+        .apply_rev();
+    assert_eq!(g_w.shape(), vec![3]);
+    let expect_w = (&x * &dy).sum_axis(Axis(0));
+    assert!(eq_tensor(g_w, &expect_w.to_vec()));
+
+    let g_b = adjoints
+        .get_mut(&l_b)
+        .expect("b adjoint missing")
+        .apply_rev();
+    assert_eq!(g_b.shape(), vec![1, 3]);
+    let expect_b = dy.sum_axis(Axis(0));
+    assert!(eq_tensor(g_b, &expect_b.to_vec()));
+
+    let g_d = adjoints
+        .get_mut(&l_d)
+        .expect("d adjoint missing")
+        .apply_rev();
+    assert!(eq_f32(
+        g_d.into(),
+        y_val.mapv(|v| -v / (d * d)).sum() as f32
+    ));
+
+    let g_p = adjoints
+        .get_mut(&l_p)
+        .expect("p adjoint missing")
+        .apply_rev();
+    assert!(eq_f32(
+        g_p.into(),
+        y_val.mapv(|v| v.powf(p) * v.ln()).sum() as f32
+    ));
+}
+
+#[test]
+fn test_type_promotion() {
+    let i = Leaf(ValType::I(3));
+    let l = Leaf(ValType::L(4));
+    let f = Leaf(ValType::F(0.5));
+    let d = Leaf(ValType::D(0.25));
+
+    //operations closed over integers keep the integer type
+    match Minus(Mul(i.clone(), i.clone()), Add(i.clone(), i.clone())).apply_fwd() {
+        ValType::I(v) => assert_eq!(v, 3),
+        x => panic!("unexpected {:?}", x),
+    }
+    match Add(i.clone(), l.clone()).apply_fwd() {
+        ValType::L(v) => assert_eq!(v, 7),
+        x => panic!("unexpected {:?}", x),
+    }
+
+    //mixing i64 and f32 gives f64
+    match Mul(l.clone(), f.clone()).apply_fwd() {
+        ValType::D(v) => assert_eq!(v, 2.),
+        x => panic!("unexpected {:?}", x),
+    }
+    match Add(i.clone(), f.clone()).apply_fwd() {
+        ValType::F(v) => assert_eq!(v, 3.5),
+        x => panic!("unexpected {:?}", x),
+    }
+
+    //non-integer operations promote integers to floats
+    match Div(i.clone(), Leaf(ValType::I(2))).apply_fwd() {
+        ValType::F(v) => assert_eq!(v, 1.5),
+        x => panic!("unexpected {:?}", x),
+    }
+    match Sin(l.clone()).apply_fwd() {
+        ValType::D(v) => assert_eq!(v, 4f64.sin()),
+        x => panic!("unexpected {:?}", x),
+    }
+
+    //f64 graphs stay f64 for values and derivatives
+    let y = Mul(Exp(Div(d.clone(), f.clone())), Pow(d.clone(), i.clone()));
+    let expect = (0.5f64).exp() * 0.25f64.powi(3);
+    match y.clone().apply_fwd() {
+        ValType::D(v) => assert!((v - expect).abs() < 1e-12),
+        x => panic!("unexpected {:?}", x),
+    }
+    let expect_d = (0.5f64).exp() * (2. * 0.25f64.powi(3) + 3. * 0.25f64.powi(2));
+    match y.rev().get_mut(&d).expect("d adjoint missing").apply_rev() {
+        ValType::D(v) => assert!((v - expect_d).abs() < 1e-12),
+        x => panic!("unexpected {:?}", x),
+    }
+}
+
+#[test]
+fn test_generic_float() {
+    //y=3*sin(x)*x^2 where x=2, with plain f64 and f32 values
+    //y'=3*cos(x)*x^2 + 6*sin(x)*x
+
+    let l0 = Leaf(2f64).active();
+    let l1 = Leaf(3f64);
+    let two = Leaf(2f64);
+    let a = Mul(Mul(Sin(l0.clone()), Pow(l0.clone(), two)), l1);
+
+    let expect = 3. * 2f64.cos() * 4. + 6. * 2f64.sin() * 2.;
+
+    assert!((a.fwd().apply_fwd() - expect).abs() < 1e-12);
+    assert!(
+        (a.rev()
+            .get_mut(&l0)
+            .expect("l0 adjoint missing")
+            .apply_rev()
+            - expect)
+            .abs()
+            < 1e-12
+    );
+
+    let l2 = Leaf(2f32).active();
+    let b = Exp(Div(l2.clone(), Leaf(4f32)));
+
+    assert!(eq_f32(b.fwd().apply_fwd(), 0.5f32.exp() / 4.));
+}
+
+#[test]
+fn test_trig_chain_rule_fwd() {
+    //y=sin(3x) where x=2, y'=3cos(3x), y''=-9sin(3x)
+    //z=cos(3x) where x=2, z'=-3sin(3x), z''=-9cos(3x)
+
+    let l0 = Leaf(ValType::F(2.)).active();
+    let l1 = Leaf(ValType::F(3.));
+    let a = Sin(Mul(l0.clone(), l1.clone()));
+    let b = Cos(Mul(l0.clone(), l1.clone()));
+
+    assert!(eq_f32(a.fwd().apply_fwd().into(), 3. * 6f32.cos()));
+    assert!(eq_f32(a.fwd().fwd().apply_fwd().into(), -9. * 6f32.sin()));
+    assert!(eq_f32(b.fwd().apply_fwd().into(), -3. * 6f32.sin()));
+    assert!(eq_f32(b.fwd().fwd().apply_fwd().into(), -9. * 6f32.cos()));
+}
+
+#[test]
+fn test_elementary_functions() {
+    use crate::valtype::ValType;
+
+    let x = Leaf(ValType::F(0.5)).active();
+    let y = Leaf(ValType::F(-2.));
+
+    //value, df/dx and d2f/dx2 at x=0.5
+    use std::f32::consts::{LN_10, LN_2, PI};
+    let h = 0.5_f32;
+    let (t, s) = (h.tanh(), 1. / (1. + (-h).exp()));
+    let (r, q) = (1. - h * h, 1. + h * h);
+    let e = 2. / PI.sqrt() * (-h * h).exp();
+    let cases: Vec<(PtrVWrap, f32, f32, f32)> = vec![
+        (
+            Sqrt(x.clone()),
+            h.sqrt(),
+            0.5 / h.sqrt(),
+            -0.25 / h.powf(1.5),
+        ),
+        (Tanh(x.clone()), t, 1. - t * t, -2. * t * (1. - t * t)),
+        (Sinh(x.clone()), h.sinh(), h.cosh(), h.sinh()),
+        (Cosh(x.clone()), h.cosh(), h.sinh(), h.cosh()),
+        (Asin(x.clone()), h.asin(), 1. / r.sqrt(), h / r.powf(1.5)),
+        (Acos(x.clone()), h.acos(), -1. / r.sqrt(), -h / r.powf(1.5)),
+        (Atan(x.clone()), h.atan(), 1. / q, -2. * h / (q * q)),
+        (
+            Log10(x.clone()),
+            h.log10(),
+            1. / (h * LN_10),
+            -1. / (h * h * LN_10),
+        ),
+        (
+            Log2(x.clone()),
+            h.log2(),
+            1. / (h * LN_2),
+            -1. / (h * h * LN_2),
+        ),
+        (
+            Sigmoid(x.clone()),
+            s,
+            s * (1. - s),
+            s * (1. - s) * (1. - 2. * s),
+        ),
+        (Softplus(x.clone()), h.exp().ln_1p(), s, s * (1. - s)),
+        (Erf(x.clone()), 0.52050, e, -2. * h * e),
+        (Neg(x.clone()), -0.5, -1., 0.),
+        (Abs(Neg(x.clone())), 0.5, 1., 0.),
+    ];
+
+    for (mut f, v, d1, d2) in cases {
+        let mut g = f.rev().get(&x).expect("x not in graph").clone();
+        assert!(eq_f32(f.apply_fwd().into(), v));
+        assert!(eq_f32(g.apply_rev().into(), d1));
+        assert!(eq_f32(g.fwd().apply_fwd().into(), d2));
+    }
+
+    //two argument functions at (x,y)=(0.5,-2)
+    let mut a = Atan2(y.clone(), x.clone());
+    assert!(eq_f32(a.apply_fwd().into(), (-2_f32).atan2(h)));
+    let adj = a.rev();
+    assert!(eq_f32(adj[&y].clone().apply_rev().into(), h / 4.25));
+    assert!(eq_f32(adj[&x].clone().apply_rev().into(), 2. / 4.25));
+
+    let mut s = Sub(x.clone(), y.clone());
+    assert!(eq_f32(s.apply_fwd().into(), 2.5));
+    let adj = s.rev();
+    assert!(eq_f32(adj[&x].clone().apply_rev().into(), 1.));
+    assert!(eq_f32(adj[&y].clone().apply_rev().into(), -1.));
+
+    let mut m = Max(x.clone(), y.clone());
+    assert!(eq_f32(m.apply_fwd().into(), 0.5));
+    let adj = m.rev();
+    assert!(eq_f32(adj[&x].clone().apply_rev().into(), 1.));
+    assert!(eq_f32(adj[&y].clone().apply_rev().into(), 0.));
+
+    let mut m = Min(x.clone(), y.clone());
+    assert!(eq_f32(m.apply_fwd().into(), -2.));
+    let adj = m.rev();
+    assert!(eq_f32(adj[&x].clone().apply_rev().into(), 0.));
+    assert!(eq_f32(adj[&y].clone().apply_rev().into(), 1.));
+
+    //ties split the derivative
+    let m = Max(x.clone(), x.clone());
+    let adj = m.rev();
+    assert!(eq_f32(adj[&x].clone().apply_rev().into(), 1.));
+
+    //float constants follow the type of the input
+    let mut l = Log10(x.clone()).rev()[&x].clone();
+    match l.apply_rev() {
+        ValType::F(_) => {}
+        v => panic!("expected f32, found {:?}", v),
+    }
+}
+
+#[test]
+fn test_rev_diamond() {
+    //paths of different lengths from x to f
+    //a=2x, b=sin(sin(a)), f=a*b+a
+    //df/dx = 2b + 2a*cos(sin(a))*cos(a) + 2
+    let x = Leaf(0.3_f64).active();
+    let a = Mul(Const(2.), x.clone());
+    let b = Sin(Sin(a.clone()));
+    let f = Add(Mul(a.clone(), b.clone()), a.clone());
+
+    let av = 0.6_f64;
+    let bv = av.sin().sin();
+    let expected = 2. * bv + 2. * av * av.sin().cos() * av.cos() + 2.;
+
+    let d = f.rev()[&x].clone().apply_rev();
+    assert!((d - expected).abs() < 1e-12);
+
+    //rev is repeatable on the same graph
+    let d = f.rev()[&x].clone().apply_rev();
+    assert!((d - expected).abs() < 1e-12);
+
+    //second order through the diamond agrees with forward over reverse
+    let g = f.rev()[&x].clone();
+    let dd_rev = g.rev()[&x].clone().apply_rev();
+    let dd_fwd = g.fwd().apply_fwd();
+    assert!((dd_rev - dd_fwd).abs() < 1e-9);
+}
+
+#[test]
+fn test_rev_deep_sharing() {
+    use crate::grad::{grad, grad_with, Mode};
+
+    //y_{k+1} = (sin(y_k) + y_k)/2, every node is used twice by its consumer
+    //dy_n/dy_0 = prod_k (cos(y_k) + 1)/2
+    let x = Leaf(0.5_f64);
+    let mut y = x.clone();
+    let mut yv = 0.5_f64;
+    let mut expected = 1.;
+    for _ in 0..60 {
+        y = Mul(Const(0.5), Add(y.clone(), Sin(y)));
+        expected *= (yv.cos() + 1.) / 2.;
+        yv = (yv.sin() + yv) / 2.;
+    }
+
+    //evaluated with memoization, a recursive walk would visit 2^60 paths
+    let d = grad(&y, std::slice::from_ref(&x))[0];
+    assert!((d - expected).abs() < 1e-9 * expected.abs());
+
+    let n = grad_with(&y, std::slice::from_ref(&x), Mode::Numeric)[0];
+    assert!((n - expected).abs() < 1e-9 * expected.abs());
+}
+
+/// identity that counts its evaluations
+#[cfg(test)]
+#[derive(Debug)]
+struct CountEval(Rc<std::cell::Cell<usize>>);
+
+#[cfg(test)]
+impl crate::custom::Operation<f64> for CountEval {
+    fn value(&self, inputs: &[f64]) -> f64 {
+        self.0.set(self.0.get() + 1);
+        inputs[0]
+    }
+    fn tangent(&self, _inputs: &[PtrVWrap<f64>], tangents: &[PtrVWrap<f64>]) -> PtrVWrap<f64> {
+        tangents[0].clone()
+    }
+    fn adjoint(
+        &self,
+        _inputs: &[PtrVWrap<f64>],
+        _output: &PtrVWrap<f64>,
+        out_adj: &PtrVWrap<f64>,
+    ) -> Vec<PtrVWrap<f64>> {
+        vec![out_adj.clone()]
+    }
+}
+
+#[test]
+fn test_cached_values() {
+    use crate::custom::Custom;
+    use std::cell::Cell;
+
+    let ca = Rc::new(Cell::new(0));
+    let cb = Rc::new(Cell::new(0));
+
+    let mut x = Leaf(2_f64);
+    let mut z = Leaf(3_f64);
+    let a = Custom(CountEval(ca.clone()), vec![Sin(x.clone())]);
+    let b = Custom(CountEval(cb.clone()), vec![Exp(z.clone())]);
+    let mut f = Mul(Add(a.clone(), b.clone()), a.clone());
+
+    let v = f.apply_fwd();
+    assert!((v - (2_f64.sin() + 3_f64.exp()) * 2_f64.sin()).abs() < 1e-12);
+    assert_eq!((ca.get(), cb.get()), (1, 1));
+
+    //nothing changed
+    assert_eq!(f.apply_fwd(), v);
+    assert_eq!((ca.get(), cb.get()), (1, 1));
+
+    //only the subgraph downstream of x is recomputed
+    x.set_val(1.);
+    let v = f.apply_fwd();
+    assert!((v - (1_f64.sin() + 3_f64.exp()) * 1_f64.sin()).abs() < 1e-12);
+    assert_eq!((ca.get(), cb.get()), (2, 1));
+
+    z.set_val(0.);
+    f.apply_fwd();
+    assert_eq!((ca.get(), cb.get()), (2, 2));
+
+    //changing the active leaf invalidates tangent graphs
+    let mut t = f.fwd();
+    x.active();
+    let dx = t.apply_fwd();
+    assert!((dx - (2. * 1_f64.sin() + 1.) * 1_f64.cos()).abs() < 1e-12);
+    x.inactive();
+    z.active();
+    assert!((t.apply_fwd() - 1_f64.sin()).abs() < 1e-12);
+}
+
+#[test]
+fn test_consumers_pruned() {
+    //derivative graphs built and dropped over a leaf that never changes
+    let x = Leaf(0.5_f64);
+    for _ in 0..1000 {
+        let f = Mul(Sin(x.clone()), x.clone());
+        let g = crate::grad::grad_graph(&f, &[x.clone()]);
+        assert_eq!(g.len(), 1);
+        f.fwd();
+    }
+    assert!(x.0.deref().borrow().consumers.len() <= 64);
+}
+
+#[test]
+fn test_cached_deep_dag() {
+    //y_{k+1} = sin(y_k * y_k), 2^200 paths from the output to x
+    let mut x = Leaf(0.5_f64);
+    let mut y = x.clone();
+    for _ in 0..200 {
+        y = Sin(Mul(y.clone(), y));
+    }
+
+    let expected = |mut v: f64| {
+        for _ in 0..200 {
+            v = (v * v).sin();
+        }
+        v
+    };
+
+    assert!((y.apply_fwd() - expected(0.5)).abs() < 1e-12);
+    x.set_val(0.9);
+    assert!((y.apply_fwd() - expected(0.9)).abs() < 1e-12);
+}
+
+/// explicit euler for y' = p*sin(y) over n steps, deep enough to overflow a
+/// recursive walk, checked against the sensitivity s=dy/dp which follows
+/// s' = p*cos(y)*s + sin(y)
+#[cfg(test)]
+fn check_time_stepping(n: usize, h: f64) {
+    use crate::grad::{grad, grad_with, Mode};
+
+    let p = Leaf(0.8_f64).active();
+    let mut y = Leaf(1_f64);
+    let (mut yv, mut sv) = (1_f64, 0_f64);
+    for _ in 0..n {
+        y = Add(y.clone(), Mul(Const(h), Mul(p.clone(), Sin(y))));
+        sv += h * (0.8 * yv.cos() * sv + yv.sin());
+        yv += h * 0.8 * yv.sin();
+    }
+
+    assert!((y.clone().apply_fwd() - yv).abs() < 1e-9);
+    assert!((y.fwd().apply_fwd() - sv).abs() < 1e-9);
+    //derivative graphs are dropped before the next one is built,
+    //without recursion like the chain of steps
+    let adj = y.rev();
+    assert!((adj[&p].clone().apply_rev() - sv).abs() < 1e-9);
+    drop(adj);
+    assert!((grad(&y, std::slice::from_ref(&p))[0] - sv).abs() < 1e-9);
+    assert!((grad_with(&y, std::slice::from_ref(&p), Mode::Numeric)[0] - sv).abs() < 1e-9);
+    drop(y);
+}
+
+#[test]
+fn test_long_time_stepping() {
+    check_time_stepping(20_000, 5e-5);
+}
+
+/// the scale the iterative walks are meant for, slow in debug builds and
+/// about 3 GB at peak, run with `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn test_long_time_stepping_stress() {
+    check_time_stepping(500_000, 2e-6);
+}
+
+#[test]
+fn test_fwd_cache_cleared_on_panic() {
+    use crate::custom::{Custom, Operation};
+
+    #[derive(Debug)]
+    struct NoTangent;
+
+    impl Operation<f64> for NoTangent {
+        fn value(&self, inputs: &[f64]) -> f64 {
+            inputs[0]
+        }
+        fn tangent(&self, _: &[PtrVWrap<f64>], _: &[PtrVWrap<f64>]) -> PtrVWrap<f64> {
+            panic!("no tangent")
+        }
+        fn adjoint(
+            &self,
+            _: &[PtrVWrap<f64>],
+            _: &PtrVWrap<f64>,
+            out_adj: &PtrVWrap<f64>,
+        ) -> Vec<PtrVWrap<f64>> {
+            vec![out_adj.clone()]
+        }
+    }
+
+    let x = Leaf(0.5_f64).active();
+    let f = Sin(Custom(NoTangent, vec![Sin(x.clone())]));
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f.fwd()));
+    assert!(r.is_err());
+    assert!(FWD_CACHE.with(|c| c.borrow().is_empty()));
+
+    //tangents of the failed sweep are not picked up by the next one
+    let g = Sin(x.clone());
+    assert!((g.fwd().apply_fwd() - 0.5_f64.cos()).abs() < 1e-12);
+    assert!(FWD_CACHE.with(|c| c.borrow().is_empty()));
+}
+
+/// rounds to the nearest integer, zero derivative almost everywhere
+#[cfg(test)]
+#[derive(Debug)]
+struct Round {}
+
+#[cfg(test)]
+impl crate::custom::Operation<f64> for Round {
+    fn value(&self, inputs: &[f64]) -> f64 {
+        inputs[0].round()
+    }
+    fn tangent(&self, inputs: &[PtrVWrap<f64>], _tangents: &[PtrVWrap<f64>]) -> PtrVWrap<f64> {
+        ZerosLike(inputs[0].clone())
+    }
+    fn adjoint(
+        &self,
+        inputs: &[PtrVWrap<f64>],
+        _output: &PtrVWrap<f64>,
+        _out_adj: &PtrVWrap<f64>,
+    ) -> Vec<PtrVWrap<f64>> {
+        vec![ZerosLike(inputs[0].clone())]
+    }
+}
+
+#[test]
+fn test_stop_gradient() {
+    let x = Leaf(1.5_f64).active();
+    let y = Leaf(0.5_f64);
+
+    //target style loss (x - sg(x*y))^2, d/dx = 2(x - xy), d/dy = 0
+    let d = Minus(x.clone(), StopGradient(Mul(x.clone(), y.clone())));
+    let mut f = Mul(d.clone(), d);
+    assert!((f.apply_fwd() - 0.75 * 0.75).abs() < 1e-12);
+
+    let g = crate::grad::grad(&f, &[x.clone(), y.clone()]);
+    assert!((g[0] - 1.5).abs() < 1e-12);
+    assert_eq!(g[1], 0.);
+    assert!((f.fwd().apply_fwd() - 1.5).abs() < 1e-12);
+    let n = crate::grad::grad_with(&f, &[x.clone(), y.clone()], crate::grad::Mode::Numeric);
+    assert_eq!(n, g);
+
+    //straight-through: value of round(x), derivative of the identity
+    let mut u = Leaf(1.5_f64);
+    let mut r = Add(
+        u.clone(),
+        StopGradient(Minus(
+            crate::custom::Custom(Round {}, vec![u.clone()]),
+            u.clone(),
+        )),
+    );
+    assert_eq!(r.apply_fwd(), 2.);
+    assert_eq!(crate::grad::grad(&r, &[u.clone()]), vec![1.]);
+    u.set_val(-0.7);
+    assert_eq!(r.apply_fwd(), -1.);
+    assert_eq!(
+        crate::grad::grad_with(&r, &[u.clone()], crate::grad::Mode::Numeric),
+        vec![1.]
+    );
+
+    //nothing propagates into the stopped subgraph, sqrt'(0) is never formed
+    let z = Leaf(0_f64);
+    let f = Add(z.clone(), StopGradient(Sqrt(z.clone())));
+    assert_eq!(crate::grad::grad(&f, &[z.clone()]), vec![1.]);
+    assert_eq!(
+        crate::grad::grad_with(&f, &[z.clone()], crate::grad::Mode::Numeric),
+        vec![1.]
+    );
+    assert_eq!(f.rev()[&z].clone().apply_rev(), 1.);
+
+    //detach snapshots the current value
+    let mut x2 = x.clone();
+    let mut s = Mul(x.clone(), x.clone()).detach();
+    x2.set_val(3.);
+    assert_eq!(s.apply_fwd(), 2.25);
+    assert_eq!(
+        crate::grad::grad(&Mul(s, x.clone()), &[x.clone()]),
+        vec![2.25]
+    );
+}
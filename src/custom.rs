@@ -0,0 +1,409 @@
+//! User defined differentiable operations
+//!
+//! An operation supplies its value together with its tangent and adjoint rules.
+//! The rules build graph nodes, so custom operations take part in forward mode,
+//! reverse mode and nested higher-order derivatives like the built-in ones.
+//! A GradientOverride keeps the value of an existing subgraph and only replaces
+//! its derivative rules.
+
+use crate::core::{FWrap, PtrVWrap, VWrap, ZerosLike};
+use crate::valtype::ValType;
+use crate::value::Value;
+use std::fmt;
+use std::rc::Rc;
+
+pub trait Operation<T: Value = ValType>: fmt::Debug {
+    /// value of the operation given the values of its inputs
+    fn value(&self, inputs: &[T]) -> T;
+
+    /// tangent of the output given the inputs and the tangents of the inputs
+    fn tangent(&self, inputs: &[PtrVWrap<T>], tangents: &[PtrVWrap<T>]) -> PtrVWrap<T>;
+
+    /// adjoints of the inputs given the inputs, the output and the adjoint of the output
+    fn adjoint(
+        &self,
+        inputs: &[PtrVWrap<T>],
+        output: &PtrVWrap<T>,
+        out_adj: &PtrVWrap<T>,
+    ) -> Vec<PtrVWrap<T>>;
+
+    /// Taylor coefficients of the output, None to derive them from the tangent rule
+    fn taylor_val(&self, _inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        None
+    }
+}
+
+/// adapter for an Operation
+struct OpCustom<T: Value> {
+    op: Rc<dyn Operation<T>>,
+}
+
+impl<T: Value> fmt::Debug for OpCustom<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OpCustom {{ op: {:?} }}", self.op)
+    }
+}
+
+impl<T: Value> FWrap<T> for OpCustom<T> {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        unreachable!("custom operations are created with Custom")
+    }
+    fn pure(&self) -> bool {
+        //user code may have side effects or hidden state
+        false
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        let op = self.op.clone();
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| {
+            let vals: Vec<T> = x.into_iter().map(|(v, _)| v).collect();
+            op.value(&vals)
+        })
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        let op = self.op.clone();
+        Box::new(move |args: Vec<PtrVWrap<T>>, _self_ptr: &PtrVWrap<T>| {
+            let tangents: Vec<PtrVWrap<T>> = args.iter().map(|i| i.fwd()).collect();
+            op.tangent(&args, &tangents)
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        let op = self.op.clone();
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, cur: &PtrVWrap<T>| {
+                let adjoints = op.adjoint(&inputs, cur, &out_adj);
+                assert_eq!(
+                    adjoints.len(),
+                    inputs.len(),
+                    "{:?} returned wrong number of adjoints",
+                    op
+                );
+                adjoints
+            },
+        )
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        self.op.taylor_val(inputs, output)
+    }
+}
+
+/// wraps a user defined operation applied to the inputs into a graph node
+#[allow(non_snake_case)]
+pub fn Custom<T: Value, O: Operation<T> + 'static>(op: O, inputs: Vec<PtrVWrap<T>>) -> PtrVWrap<T> {
+    VWrap::new_with_input(Box::new(OpCustom { op: Rc::new(op) }), inputs)
+}
+
+pub trait GradientOverride<T: Value = ValType>: fmt::Debug {
+    /// tangent of the output given the inputs of the subgraph and their tangents
+    fn tangent(
+        &self,
+        inputs: &[PtrVWrap<T>],
+        output: &PtrVWrap<T>,
+        tangents: &[PtrVWrap<T>],
+    ) -> PtrVWrap<T>;
+
+    /// adjoints of the inputs of the subgraph given the output and its adjoint
+    fn adjoint(
+        &self,
+        inputs: &[PtrVWrap<T>],
+        output: &PtrVWrap<T>,
+        out_adj: &PtrVWrap<T>,
+    ) -> Vec<PtrVWrap<T>>;
+
+    /// Taylor coefficients of the output, None to derive them from the tangent rule
+    fn taylor_val(&self, _inputs: &[Vec<T>], _output: &T) -> Option<Vec<T>> {
+        None
+    }
+}
+
+/// adapter for a GradientOverride, the first input is the wrapped subgraph
+struct OpOverride<T: Value> {
+    rules: Rc<dyn GradientOverride<T>>,
+}
+
+impl<T: Value> fmt::Debug for OpOverride<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OpOverride {{ rules: {:?} }}", self.rules)
+    }
+}
+
+impl<T: Value> FWrap<T> for OpOverride<T> {
+    fn new() -> Box<dyn FWrap<T>>
+    where
+        Self: Sized,
+    {
+        unreachable!("gradient overrides are created with CustomGradient")
+    }
+    fn pure(&self) -> bool {
+        //equal subgraphs may carry different rules
+        false
+    }
+    fn f(&self) -> Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T> {
+        Box::new(move |x: Vec<(T, Option<T>)>, _v: Option<T>| x[0].0.clone())
+    }
+    fn tangent(&self) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, &PtrVWrap<T>) -> PtrVWrap<T>> {
+        let rules = self.rules.clone();
+        Box::new(move |args: Vec<PtrVWrap<T>>, self_ptr: &PtrVWrap<T>| {
+            let tangents: Vec<PtrVWrap<T>> = args[1..].iter().map(|i| i.fwd()).collect();
+            rules.tangent(&args[1..], self_ptr, &tangents)
+        })
+    }
+    fn adjoint(
+        &self,
+    ) -> Box<dyn FnMut(Vec<PtrVWrap<T>>, PtrVWrap<T>, &PtrVWrap<T>) -> Vec<PtrVWrap<T>>> {
+        let rules = self.rules.clone();
+        Box::new(
+            move |inputs: Vec<PtrVWrap<T>>, out_adj: PtrVWrap<T>, cur: &PtrVWrap<T>| {
+                let adjoints = rules.adjoint(&inputs[1..], cur, &out_adj);
+                assert_eq!(
+                    adjoints.len(),
+                    inputs.len() - 1,
+                    "{:?} returned wrong number of adjoints",
+                    rules
+                );
+                //placeholder, the subgraph edge is skipped by stops_gradient
+                let mut res = vec![ZerosLike(inputs[0].clone())];
+                res.extend(adjoints);
+                res
+            },
+        )
+    }
+    fn stops_gradient(&self, k: usize) -> bool {
+        //the wrapped subgraph is never differentiated
+        k == 0
+    }
+    fn taylor_val(&self, inputs: &[Vec<T>], output: &T) -> Option<Vec<T>> {
+        self.rules.taylor_val(&inputs[1..], output)
+    }
+}
+
+/// output of a subgraph with derivatives taken from rules instead of the subgraph
+///
+/// the value is that of output, fwd() and rev() apply the rules to the given
+/// inputs of the subgraph
+#[allow(non_snake_case)]
+pub fn CustomGradient<T: Value, O: GradientOverride<T> + 'static>(
+    rules: O,
+    output: PtrVWrap<T>,
+    inputs: Vec<PtrVWrap<T>>,
+) -> PtrVWrap<T> {
+    let mut inp = vec![output];
+    inp.extend(inputs);
+    VWrap::new_with_input(
+        Box::new(OpOverride {
+            rules: Rc::new(rules),
+        }),
+        inp,
+    )
+}
+
+#[cfg(test)]
+use crate::core::{Add, Const, Div, Exp, Leaf, Ln, Max, Min, Minus, Mul, Sin};
+
+/// y = sqrt(x), y' = x'/(2y)
+#[cfg(test)]
+#[derive(Debug)]
+struct Sqrt {}
+
+#[cfg(test)]
+impl Operation<f64> for Sqrt {
+    fn value(&self, inputs: &[f64]) -> f64 {
+        inputs[0].sqrt()
+    }
+    fn tangent(&self, inputs: &[PtrVWrap<f64>], tangents: &[PtrVWrap<f64>]) -> PtrVWrap<f64> {
+        let y = Custom(Sqrt {}, vec![inputs[0].clone()]);
+        Div(tangents[0].clone(), Mul(Const(2.), y))
+    }
+    fn adjoint(
+        &self,
+        _inputs: &[PtrVWrap<f64>],
+        output: &PtrVWrap<f64>,
+        out_adj: &PtrVWrap<f64>,
+    ) -> Vec<PtrVWrap<f64>> {
+        vec![Div(out_adj.clone(), Mul(Const(2.), output.clone()))]
+    }
+}
+
+#[test]
+fn test_custom_op() {
+    //y = sqrt(3x) where x=3
+    //y' = 3/(2 sqrt(3x))
+    //y'' = -9/(4 (3x)^(3/2))
+
+    let l0 = Leaf(3f64).active();
+    let a = Custom(Sqrt {}, vec![Mul(Leaf(3.), l0.clone())]);
+
+    assert!((a.clone().apply_fwd() - 3.).abs() < 1e-12);
+
+    let d1 = 3. / 6.;
+    let d2 = -9. / (4. * 27.);
+
+    assert!((a.fwd().apply_fwd() - d1).abs() < 1e-12);
+    assert!((a.fwd().fwd().apply_fwd() - d2).abs() < 1e-12);
+
+    let mut g = a.rev().get_mut(&l0).expect("l0 adjoint missing").clone();
+    assert!((g.apply_rev() - d1).abs() < 1e-12);
+    assert!((g.fwd().apply_fwd() - d2).abs() < 1e-12);
+    assert!(
+        (g.rev()
+            .get_mut(&l0)
+            .expect("l0 adjoint missing")
+            .apply_rev()
+            - d2)
+            .abs()
+            < 1e-12
+    );
+}
+
+/// y = x^2 with an adjoint rule that runs its own reverse sweep over the input
+#[cfg(test)]
+#[derive(Debug)]
+struct SquareNested {}
+
+#[cfg(test)]
+impl Operation<f64> for SquareNested {
+    fn value(&self, inputs: &[f64]) -> f64 {
+        inputs[0] * inputs[0]
+    }
+    fn tangent(&self, inputs: &[PtrVWrap<f64>], tangents: &[PtrVWrap<f64>]) -> PtrVWrap<f64> {
+        Mul(Mul(Const(2.), inputs[0].clone()), tangents[0].clone())
+    }
+    fn adjoint(
+        &self,
+        inputs: &[PtrVWrap<f64>],
+        _output: &PtrVWrap<f64>,
+        out_adj: &PtrVWrap<f64>,
+    ) -> Vec<PtrVWrap<f64>> {
+        //sweep over the input subgraph while the outer sweep is in flight
+        let inner = Mul(inputs[0].clone(), inputs[0].clone()).rev();
+        assert!(!inner.is_empty());
+        vec![Mul(Mul(Const(2.), inputs[0].clone()), out_adj.clone())]
+    }
+}
+
+#[test]
+fn test_nested_rev() {
+    //f = sin(x)*x + (sin(x)*x)^2, the custom op shares sin(x)*x with the outer graph
+    let x = Leaf(0.8_f64);
+    let s = Mul(Sin(x.clone()), x.clone());
+    let f = Add(s.clone(), Custom(SquareNested {}, vec![s.clone()]));
+
+    let sv = 0.8_f64.sin() * 0.8;
+    let ds = 0.8_f64.cos() * 0.8 + 0.8_f64.sin();
+    let expected = ds + 2. * sv * ds;
+
+    let mut g = f.rev()[&x].clone();
+    assert!((g.apply_rev() - expected).abs() < 1e-12);
+
+    //repeated sweeps need no reset
+    let mut g = f.rev()[&x].clone();
+    assert!((g.apply_rev() - expected).abs() < 1e-12);
+}
+
+/// derivative of log(exp(a)+exp(b)) as softmax weights exp(x-y)
+#[cfg(test)]
+#[derive(Debug)]
+struct LogSumExp {}
+
+#[cfg(test)]
+impl GradientOverride<f64> for LogSumExp {
+    fn tangent(
+        &self,
+        inputs: &[PtrVWrap<f64>],
+        output: &PtrVWrap<f64>,
+        tangents: &[PtrVWrap<f64>],
+    ) -> PtrVWrap<f64> {
+        let w = |x: &PtrVWrap<f64>| Exp(Minus(x.clone(), output.clone()));
+        Add(
+            Mul(w(&inputs[0]), tangents[0].clone()),
+            Mul(w(&inputs[1]), tangents[1].clone()),
+        )
+    }
+    fn adjoint(
+        &self,
+        inputs: &[PtrVWrap<f64>],
+        output: &PtrVWrap<f64>,
+        out_adj: &PtrVWrap<f64>,
+    ) -> Vec<PtrVWrap<f64>> {
+        inputs
+            .iter()
+            .map(|x| Mul(Exp(Minus(x.clone(), output.clone())), out_adj.clone()))
+            .collect()
+    }
+}
+
+/// straight-through derivative of a clipped value
+#[cfg(test)]
+#[derive(Debug)]
+struct StraightThrough {}
+
+#[cfg(test)]
+impl GradientOverride<f64> for StraightThrough {
+    fn tangent(
+        &self,
+        _inputs: &[PtrVWrap<f64>],
+        _output: &PtrVWrap<f64>,
+        tangents: &[PtrVWrap<f64>],
+    ) -> PtrVWrap<f64> {
+        tangents[0].clone()
+    }
+    fn adjoint(
+        &self,
+        _inputs: &[PtrVWrap<f64>],
+        _output: &PtrVWrap<f64>,
+        out_adj: &PtrVWrap<f64>,
+    ) -> Vec<PtrVWrap<f64>> {
+        vec![out_adj.clone()]
+    }
+}
+
+#[test]
+fn test_gradient_override() {
+    use crate::grad::{eval, grad, grad_with, Mode};
+
+    let a = Leaf(1_f64).active();
+    let b = Leaf(0.5_f64);
+    let lse = Ln(Add(Exp(a.clone()), Exp(b.clone())));
+    let f = Mul(
+        CustomGradient(LogSumExp {}, lse.clone(), vec![a.clone(), b.clone()]),
+        a.clone(),
+    );
+
+    let y = eval(&[lse.clone()])[0];
+    let (wa, wb) = ((1. - y).exp(), (0.5 - y).exp());
+    assert!((eval(&[f.clone()])[0] - y).abs() < 1e-12);
+
+    //d/da (lse*a) = wa*a + lse, d/db = wb*a
+    let g = grad(&f, &[a.clone(), b.clone()]);
+    assert!((g[0] - (wa + y)).abs() < 1e-12);
+    assert!((g[1] - wb).abs() < 1e-12);
+    assert_eq!(grad_with(&f, &[a.clone(), b.clone()], Mode::Numeric), g);
+    assert!((eval(&[f.fwd()])[0] - g[0]).abs() < 1e-12);
+
+    //second derivative through the rules, d2/da2 = 2wa + a wa(1-wa)
+    let h = grad(&crate::grad::grad_graph(&f, &[a.clone()])[0], &[a.clone()]);
+    assert!((h[0] - (2. * wa + wa * (1. - wa))).abs() < 1e-12);
+
+    //clip to [-1,1] passes the gradient through outside the range
+    let x = Leaf(3_f64);
+    let clip = Max(Min(x.clone(), Const(1.)), Const(-1.));
+    assert_eq!(grad(&clip, &[x.clone()]), vec![0.]);
+    let st = CustomGradient(StraightThrough {}, clip, vec![x.clone()]);
+    assert_eq!(eval(&[st.clone()]), vec![1.]);
+    assert_eq!(grad(&st, &[x.clone()]), vec![1.]);
+
+    //the subgraph is not walked, its infinite derivative at 0 does not leak in
+    let z = Leaf(0_f64);
+    let st = CustomGradient(
+        StraightThrough {},
+        crate::core::Sqrt(z.clone()),
+        vec![z.clone()],
+    );
+    assert_eq!(st.rev()[&z].clone().apply_rev(), 1.);
+    assert_eq!(grad(&st, &[z.clone()]), vec![1.]);
+    assert_eq!(grad_with(&st, &[z.clone()], Mode::Numeric), vec![1.]);
+}
@@ -0,0 +1,113 @@
+//! Vector forward mode
+//!
+//! Every node carries a block of tangents, one per direction, computed on plain
+//! values through FWrap::tangent_val. One sweep gives the derivatives along all
+//! directions. Seeds are passed per call, so the active flags of the leaves are
+//! neither read nor changed.
+
+use crate::core::{topo_order, PtrVWrap};
+use crate::grad::eval;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// tangents of the outputs given a block of seeds for each input
+///
+/// seeds[i][k] is the tangent of inputs[i] in direction k, the result holds the
+/// tangents of each output in the same directions. Nodes not depending on any
+/// input get zero tangents without evaluating tangent rules.
+#[allow(clippy::mutable_key_type)]
+pub fn fwd_vec<T: Value>(
+    outputs: &[PtrVWrap<T>],
+    inputs: &[PtrVWrap<T>],
+    seeds: &[Vec<T>],
+) -> Vec<Vec<T>> {
+    assert_eq!(inputs.len(), seeds.len(), "one block of seeds per input");
+    let dirs = seeds.first().map_or(0, |s| s.len());
+    assert!(
+        seeds.iter().all(|s| s.len() == dirs),
+        "seed blocks differ in length"
+    );
+
+    let seeded: HashMap<&PtrVWrap<T>, &Vec<T>> = inputs.iter().zip(seeds).collect();
+
+    let order = topo_order(outputs);
+    let vals = eval(&order);
+    let index: HashMap<PtrVWrap<T>, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(k, n)| (n.clone(), k))
+        .collect();
+
+    //None for nodes not depending on any input
+    let mut tangents: Vec<Option<Vec<T>>> = Vec::with_capacity(order.len());
+    for (k, n) in order.iter().enumerate() {
+        if let Some(s) = seeded.get(n) {
+            tangents.push(Some((*s).clone()));
+            continue;
+        }
+
+        let inp: Vec<usize> = n.0.deref().borrow().inp.iter().map(|i| index[i]).collect();
+        if inp.iter().all(|&i| tangents[i].is_none()) {
+            tangents.push(None);
+            continue;
+        }
+
+        let raw = n.0.deref().borrow().raw.clone();
+        let inp_vals: Vec<T> = inp.iter().map(|&i| vals[i].clone()).collect();
+        let block = (0..dirs)
+            .map(|d| {
+                let inp_tangents: Vec<T> = inp
+                    .iter()
+                    .map(|&i| match &tangents[i] {
+                        Some(t) => t[d].clone(),
+                        None => vals[i].zeros_like(),
+                    })
+                    .collect();
+                raw.tangent_val(&inp_vals, &vals[k], &inp_tangents)
+            })
+            .collect();
+        tangents.push(Some(block));
+    }
+
+    outputs
+        .iter()
+        .map(|o| {
+            let k = index[o];
+            match &tangents[k] {
+                Some(t) => t.clone(),
+                None => vec![vals[k].zeros_like(); dirs],
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_fwd_vec() {
+    use crate::core::*;
+
+    let x = Leaf(0.3_f64);
+    let y = Leaf(1.2_f64);
+    let z = Leaf(-0.7_f64);
+    let f0 = Mul(Sin(x.clone()), Exp(y.clone()));
+    let f1 = Div(Ln(y.clone()), Add(Tanh(x.clone()), Const(2.)));
+    let f2 = Pow(x.clone(), Const(2.)) + Atan2(Softplus(z.clone()), Cosh(x.clone()));
+    let outputs = vec![f0, f1, f2, Const(1.)];
+    let inputs = vec![x.clone(), y.clone(), z.clone()];
+
+    //identity seeds give the jacobian in one sweep
+    let seeds: Vec<Vec<f64>> = (0..3)
+        .map(|i| (0..3).map(|d| if i == d { 1. } else { 0. }).collect())
+        .collect();
+    let t = fwd_vec(&outputs, &inputs, &seeds);
+
+    let j = crate::jacobian::jacobian_rev(&outputs, &inputs);
+    for r in 0..4 {
+        for c in 0..3 {
+            assert!((t[r][c] - j[[r, c]]).abs() < 1e-12);
+        }
+    }
+
+    //active flags are left alone
+    assert_eq!(x.seed_state(), (false, None));
+}
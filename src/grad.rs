@@ -0,0 +1,211 @@
+//! Gradients of an output with respect to several inputs
+//!
+//! The adjoint graphs returned by rev() share subgraphs, calling apply_rev()
+//! on each of them re-evaluates the shared parts. The functions here evaluate
+//! a set of graphs in one sweep where every node is computed once.
+
+use crate::core::{rev_seeded, topo_order, Const, PtrVWrap, ZerosLike};
+use crate::tape::Tape;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// evaluates the graphs rooted at nodes, shared nodes are evaluated once
+/// and cached values are reused
+#[allow(clippy::mutable_key_type)]
+pub fn eval<T: Value>(nodes: &[PtrVWrap<T>]) -> Vec<T> {
+    let mut memo: HashMap<PtrVWrap<T>, T> = HashMap::new();
+
+    for n in topo_order(nodes) {
+        let v = match n.cached() {
+            Some(v) => v,
+            None => {
+                let args: Vec<(T, Option<T>)> =
+                    n.0.deref()
+                        .borrow()
+                        .inp
+                        .iter()
+                        .map(|i| (memo[i].clone(), i.0.deref().borrow().seed_arg(&memo[i])))
+                        .collect();
+                n.compute(args)
+            }
+        };
+        memo.insert(n, v);
+    }
+
+    nodes.iter().map(|n| memo[n].clone()).collect()
+}
+
+/// adjoint graphs of output with respect to each of inputs,
+/// inputs that output does not depend on get a zero graph
+///
+/// inputs may be leaves or intermediate nodes of the graph
+#[allow(clippy::mutable_key_type)]
+pub fn grad_graph<T: Value>(output: &PtrVWrap<T>, inputs: &[PtrVWrap<T>]) -> Vec<PtrVWrap<T>> {
+    let adjoints = output.rev_wrt(inputs);
+    inputs
+        .iter()
+        .map(|i| match adjoints.get(i) {
+            Some(a) => a.clone(),
+            None => ZerosLike(i.clone()),
+        })
+        .collect()
+}
+
+/// adjoint graphs of the seed weighted sum of outputs with respect to each of inputs,
+/// seeds[k] is the adjoint of outputs[k] and shaped like it
+#[allow(clippy::mutable_key_type)]
+pub fn vjp_graph<T: Value>(
+    outputs: &[PtrVWrap<T>],
+    seeds: &[PtrVWrap<T>],
+    inputs: &[PtrVWrap<T>],
+) -> Vec<PtrVWrap<T>> {
+    let adjoints = rev_seeded(outputs, seeds.to_vec(), inputs);
+    inputs
+        .iter()
+        .map(|i| match adjoints.get(i) {
+            Some(a) => a.clone(),
+            None => ZerosLike(i.clone()),
+        })
+        .collect()
+}
+
+/// vector-Jacobian product u^T J of the outputs with respect to each of inputs
+/// in one reverse sweep, u is given by the seeds
+pub fn vjp<T: Value>(outputs: &[PtrVWrap<T>], seeds: &[T], inputs: &[PtrVWrap<T>]) -> Vec<T> {
+    let seeds: Vec<PtrVWrap<T>> = seeds.iter().map(|s| Const(s.clone())).collect();
+    eval(&vjp_graph(outputs, &seeds, inputs))
+}
+
+/// evaluation path for gradients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// builds adjoint graphs and evaluates them, as rev() does
+    Symbolic,
+    /// back-propagates plain values on a tape, no graph is built except for
+    /// the adjoint rules of custom operations
+    Numeric,
+}
+
+/// gradient of output with respect to each of inputs
+pub fn grad<T: Value>(output: &PtrVWrap<T>, inputs: &[PtrVWrap<T>]) -> Vec<T> {
+    grad_with(output, inputs, Mode::Symbolic)
+}
+
+/// gradient of output with respect to each of inputs using the given evaluation path
+pub fn grad_with<T: Value>(output: &PtrVWrap<T>, inputs: &[PtrVWrap<T>], mode: Mode) -> Vec<T> {
+    match mode {
+        Mode::Symbolic => eval(&grad_graph(output, inputs)),
+        Mode::Numeric => Tape::record(output).gradient(inputs),
+    }
+}
+
+#[test]
+fn test_grad() {
+    use crate::core::*;
+
+    let x = Leaf(0.5_f64).active();
+    let y = Leaf(2_f64);
+    let z = Leaf(-1_f64);
+
+    //shared subexpression s=sin(x*y)
+    let s = (&x * &y).sin();
+    let f = &s * &s + &s * &y;
+
+    let g = grad(&f, &[x.clone(), y.clone(), z.clone()]);
+
+    let adj = f.rev();
+    assert!((g[0] - adj[&x].clone().apply_rev()).abs() < 1e-12);
+    assert!((g[1] - adj[&y].clone().apply_rev()).abs() < 1e-12);
+    assert_eq!(g[2], 0.);
+
+    //df/dx=(2s+y)*cos(xy)*y
+    let c = 1_f64.cos();
+    let expected = (2. * 1_f64.sin() + 2.) * c * 2.;
+    assert!((g[0] - expected).abs() < 1e-12);
+
+    //symbolic adjoints can be differentiated further
+    let gg = grad_graph(&f, &[x.clone(), y.clone()]);
+    let h = grad(&gg[0], &[x.clone(), y.clone()]);
+    assert!((h[0] - gg[0].fwd().apply_fwd()).abs() < 1e-12);
+    assert!((h[1] - grad(&gg[1], &[x.clone(), y.clone()])[0]).abs() < 1e-12);
+}
+
+#[test]
+fn test_eval_shared() {
+    use crate::core::*;
+
+    let x = Leaf(2_f64);
+    let s = x.exp();
+    let a = &s + 1;
+    let b = &s * &x;
+    assert_eq!(
+        eval(&[a.clone(), b.clone(), s.clone()]),
+        vec![
+            a.clone().apply_fwd(),
+            b.clone().apply_fwd(),
+            s.clone().apply_fwd()
+        ]
+    );
+}
+
+#[test]
+fn test_vjp() {
+    use crate::core::*;
+
+    let x = Leaf(0.5_f64);
+    let y = Leaf(2_f64);
+    let outputs = vec![
+        Mul(x.clone(), Cos(y.clone())),
+        Mul(x.clone(), Sin(y.clone())),
+        Mul(x.clone(), y.clone()),
+    ];
+    let inputs = vec![x.clone(), y.clone()];
+
+    let u = [0.5, -1., 2.];
+    let j = crate::jacobian::jacobian_rev(&outputs, &inputs);
+    let r = vjp(&outputs, &u, &inputs);
+    for c in 0..2 {
+        let e: f64 = (0..3).map(|k| u[k] * j[[k, c]]).sum();
+        assert!((r[c] - e).abs() < 1e-12);
+    }
+
+    //the same output twice accumulates its seeds
+    let r = vjp(
+        &[outputs[2].clone(), outputs[2].clone()],
+        &[1., 2.],
+        &inputs,
+    );
+    assert!((r[0] - 3. * 2.).abs() < 1e-12);
+    assert!((r[1] - 3. * 0.5).abs() < 1e-12);
+}
+
+#[test]
+fn test_grad_intermediate() {
+    use crate::core::*;
+
+    let x = Leaf(0.5_f64);
+    let y = Leaf(2_f64);
+    //named subexpressions h and s
+    let h = Mul(x.clone(), y.clone());
+    let s = Sin(h.clone());
+    let f = Add(Mul(s.clone(), s.clone()), Mul(h.clone(), y.clone()));
+
+    //df/ds = 2s, df/dh = 2s cos(h) + y
+    let (hv, sv) = (1_f64, 1_f64.sin());
+    let expected = [2. * sv, 2. * sv * hv.cos() + 2.];
+
+    let wrt = [s.clone(), h.clone(), x.clone()];
+    let g = grad(&f, &wrt);
+    let n = grad_with(&f, &wrt, Mode::Numeric);
+    for k in 0..2 {
+        assert!((g[k] - expected[k]).abs() < 1e-12);
+        assert!((n[k] - expected[k]).abs() < 1e-12);
+    }
+    //leaves come back alongside
+    assert!((g[2] - expected[1] * 2.).abs() < 1e-12);
+    assert!(f.rev_wrt(&[h.clone()]).contains_key(&y));
+
+    //the root itself has unit adjoint, unrelated nodes zero
+    assert_eq!(grad(&f, &[f.clone(), Leaf(1.)]), vec![1., 0.]);
+}
@@ -0,0 +1,180 @@
+//! Jacobians of functions with several outputs and Hessians of scalar functions
+//!
+//! Forward mode costs one tangent sweep per input and reverse mode one adjoint
+//! sweep per output, jacobian() picks the cheaper one. Hessians are computed
+//! forward-over-reverse from a single set of gradient graphs.
+
+use crate::core::PtrVWrap;
+use crate::forward::fwd_vec;
+use crate::grad::{eval, grad, grad_graph};
+use crate::value::Value;
+use ndarray::Array2;
+
+/// derivatives of each output (rows) with respect to each input leaf (columns)
+pub fn jacobian<T: Value>(outputs: &[PtrVWrap<T>], inputs: &[PtrVWrap<T>]) -> Array2<T> {
+    if inputs.len() <= outputs.len() {
+        jacobian_fwd(outputs, inputs)
+    } else {
+        jacobian_rev(outputs, inputs)
+    }
+}
+
+/// one column per input, all columns come from one vector forward sweep
+pub(crate) fn jacobian_fwd<T: Value>(outputs: &[PtrVWrap<T>], inputs: &[PtrVWrap<T>]) -> Array2<T> {
+    let vals = eval(inputs);
+    let seeds: Vec<Vec<T>> = vals
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            (0..inputs.len())
+                .map(|d| {
+                    if i == d {
+                        v.ones_like()
+                    } else {
+                        v.zeros_like()
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let columns = fwd_vec(outputs, inputs, &seeds);
+
+    Array2::from_shape_fn((outputs.len(), inputs.len()), |(r, c)| {
+        columns[r][c].clone()
+    })
+}
+
+/// Jacobian of the outputs times the vector v in one forward sweep
+///
+/// each input is seeded with its entry of v, the Jacobian is not formed
+pub fn jvp<T: Value>(outputs: &[PtrVWrap<T>], inputs: &[PtrVWrap<T>], v: &[T]) -> Vec<T> {
+    assert_eq!(inputs.len(), v.len(), "direction has wrong length");
+    let seeds: Vec<Vec<T>> = v.iter().map(|v| vec![v.clone()]).collect();
+    fwd_vec(outputs, inputs, &seeds)
+        .into_iter()
+        .map(|mut t| t.pop().expect("tangent missing"))
+        .collect()
+}
+
+/// one row per output
+pub(crate) fn jacobian_rev<T: Value>(outputs: &[PtrVWrap<T>], inputs: &[PtrVWrap<T>]) -> Array2<T> {
+    let rows: Vec<Vec<T>> = outputs.iter().map(|o| grad(o, inputs)).collect();
+    Array2::from_shape_fn((outputs.len(), inputs.len()), |(r, c)| rows[r][c].clone())
+}
+
+/// second derivatives of f with respect to each pair of input leaves
+///
+/// the gradient graphs are built once by reverse mode and differentiated by
+/// vector forward mode in one sweep
+pub fn hessian<T: Value>(f: &PtrVWrap<T>, inputs: &[PtrVWrap<T>]) -> Array2<T> {
+    jacobian_fwd(&grad_graph(f, inputs), inputs)
+}
+
+/// Hessian of f times the vector v without forming the Hessian
+///
+/// computed forward-over-reverse as the directional derivative of the gradient
+/// along v, the cost is a small multiple of one gradient evaluation
+pub fn hvp<T: Value>(f: &PtrVWrap<T>, inputs: &[PtrVWrap<T>], v: &[T]) -> Vec<T> {
+    jvp(&grad_graph(f, inputs), inputs, v)
+}
+
+#[test]
+fn test_jacobian() {
+    use crate::core::*;
+
+    let x = Leaf(0.5_f64);
+    let y = Leaf(2_f64).active();
+
+    //polar to cartesian like map with three outputs
+    let outputs = vec![
+        Mul(x.clone(), Cos(y.clone())),
+        Mul(x.clone(), Sin(y.clone())),
+        Mul(x.clone(), y.clone()),
+    ];
+    let inputs = vec![x.clone(), y.clone()];
+
+    let expected = ndarray::arr2(&[
+        [2_f64.cos(), -0.5 * 2_f64.sin()],
+        [2_f64.sin(), 0.5 * 2_f64.cos()],
+        [2., 0.5],
+    ]);
+
+    let jf = jacobian_fwd(&outputs, &inputs);
+    let jr = jacobian_rev(&outputs, &inputs);
+    for ((e, f), r) in expected.iter().zip(jf.iter()).zip(jr.iter()) {
+        assert!((e - f).abs() < 1e-12);
+        assert!((e - r).abs() < 1e-12);
+    }
+    assert_eq!(jacobian(&outputs, &inputs).dim(), (3, 2));
+
+    //transposed problem goes through reverse mode
+    let jt = jacobian(&outputs[..1], &inputs);
+    assert_eq!(jt.dim(), (1, 2));
+    assert!((jt[[0, 1]] - expected[[0, 1]]).abs() < 1e-12);
+
+    //activation flags are left alone
+    assert!(!x.0.borrow().eval_g);
+    assert!(y.0.borrow().eval_g);
+}
+
+#[test]
+fn test_hessian() {
+    use crate::core::*;
+
+    //rosenbrock f=(1-x)^2+100(y-x^2)^2
+    let x = Leaf(0.5_f64);
+    let y = Leaf(-1.5_f64);
+    let a = Minus(Const(1.), x.clone());
+    let b = Minus(y.clone(), Mul(x.clone(), x.clone()));
+    let f = Add(Mul(a.clone(), a), Mul(Const(100.), Mul(b.clone(), b)));
+
+    let (xv, yv) = (0.5, -1.5);
+    let expected = ndarray::arr2(&[
+        [2. - 400. * (yv - 3. * xv * xv), -400. * xv],
+        [-400. * xv, 200.],
+    ]);
+
+    let inputs = vec![x.clone(), y.clone()];
+    let h = hessian(&f, &inputs);
+    for (e, h) in expected.iter().zip(h.iter()) {
+        assert!((e - h).abs() < 1e-9);
+    }
+
+    let v = [0.3, -2.];
+    let hv = hvp(&f, &inputs, &v);
+    for r in 0..2 {
+        let e = expected[[r, 0]] * v[0] + expected[[r, 1]] * v[1];
+        assert!((hv[r] - e).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_jvp() {
+    use crate::core::*;
+
+    let mut x = Leaf(0.7_f64);
+    let y = Leaf(-0.4_f64);
+    let f0 = Mul(x.clone(), y.clone());
+    let f1 = Sin(Add(x.clone(), Mul(y.clone(), y.clone())));
+    let outputs = vec![f0, f1];
+    let inputs = vec![x.clone(), y.clone()];
+
+    x.active();
+    let j = jacobian(&outputs, &inputs);
+    let v = [1.5, -0.25];
+    let jv = jvp(&outputs, &inputs, &v);
+    for r in 0..2 {
+        let e = j[[r, 0]] * v[0] + j[[r, 1]] * v[1];
+        assert!((jv[r] - e).abs() < 1e-12);
+    }
+
+    //seeds are left alone
+    assert_eq!(x.seed_state(), (true, None));
+    assert_eq!(y.seed_state(), (false, None));
+
+    //a single seeded leaf scales its tangent
+    let mut y = y;
+    y.seed(3.);
+    let t = outputs[0].fwd();
+    assert!((eval(&[t])[0] - (3. * 0.7 - 0.4)).abs() < 1e-12);
+}
@@ -7,13 +7,35 @@
 extern crate lazy_static;
 
 mod core;
+mod custom;
+mod forward;
+mod grad;
+mod jacobian;
+mod operators;
+#[cfg(test)]
+mod prop_test;
 mod ricci;
+mod simplify;
+mod tape;
+mod taylor;
 mod valtype;
+mod value;
 
 mod interface {
-    pub use crate::core::{Add, Cos, Div, Exp, Leaf, Ln, Mul, Pow, Sin, Tan};
+    pub use crate::core::{
+        Abs, Acos, Add, Asin, Atan, Atan2, Const, Cos, Cosh, Div, Erf, Exp, Leaf, Ln, Log10, Log2,
+        MatMul, Max, Mean, MeanAxis, Min, Minus, Mul, Neg, Pow, PtrVWrap, Reshape, Sigmoid, Sin,
+        Sinh, Softplus, Sqrt, StopGradient, Sub, Sum, SumAxis, Tan, Tanh, Transpose,
+    };
+    pub use crate::custom::{Custom, CustomGradient, GradientOverride, Operation};
+    pub use crate::forward::fwd_vec;
+    pub use crate::grad::{eval, grad, grad_graph, grad_with, vjp, vjp_graph, Mode};
+    pub use crate::jacobian::{hessian, hvp, jacobian, jvp};
     pub use crate::ricci::*;
+    pub use crate::tape::Tape;
+    pub use crate::taylor::taylor;
     pub use crate::valtype::ValType;
+    pub use crate::value::Value;
 }
 
 pub use interface::*;
@@ -0,0 +1,202 @@
+//! Operator overloading and method forms for building graphs
+//!
+//! `(x + y) * x` builds the same graph as `Mul(Add(x.clone(), y.clone()), x.clone())`,
+//! scalar operands become Const nodes.
+
+use crate::core::*;
+use crate::value::Value;
+use std::ops;
+
+macro_rules! binary_op {
+    ($trait:ident, $method:ident, $func:ident) => {
+        impl<T: Value> ops::$trait<PtrVWrap<T>> for PtrVWrap<T> {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: PtrVWrap<T>) -> PtrVWrap<T> {
+                $func(self, other)
+            }
+        }
+
+        impl<T: Value> ops::$trait<&PtrVWrap<T>> for PtrVWrap<T> {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: &PtrVWrap<T>) -> PtrVWrap<T> {
+                $func(self, other.clone())
+            }
+        }
+
+        impl<T: Value> ops::$trait<PtrVWrap<T>> for &PtrVWrap<T> {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: PtrVWrap<T>) -> PtrVWrap<T> {
+                $func(self.clone(), other)
+            }
+        }
+
+        impl<T: Value> ops::$trait<&PtrVWrap<T>> for &PtrVWrap<T> {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: &PtrVWrap<T>) -> PtrVWrap<T> {
+                $func(self.clone(), other.clone())
+            }
+        }
+
+        scalar_op!($trait, $method, $func, f64, from_f64);
+        scalar_op!($trait, $method, $func, i32, from_i32);
+    };
+}
+
+macro_rules! scalar_op {
+    ($trait:ident, $method:ident, $func:ident, $scalar:ty, $from:ident) => {
+        impl<T: Value> ops::$trait<$scalar> for PtrVWrap<T> {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: $scalar) -> PtrVWrap<T> {
+                $func(self, Const(T::$from(other)))
+            }
+        }
+
+        impl<T: Value> ops::$trait<$scalar> for &PtrVWrap<T> {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: $scalar) -> PtrVWrap<T> {
+                $func(self.clone(), Const(T::$from(other)))
+            }
+        }
+
+        impl<T: Value> ops::$trait<PtrVWrap<T>> for $scalar {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: PtrVWrap<T>) -> PtrVWrap<T> {
+                $func(Const(T::$from(self)), other)
+            }
+        }
+
+        impl<T: Value> ops::$trait<&PtrVWrap<T>> for $scalar {
+            type Output = PtrVWrap<T>;
+            fn $method(self, other: &PtrVWrap<T>) -> PtrVWrap<T> {
+                $func(Const(T::$from(self)), other.clone())
+            }
+        }
+    };
+}
+
+binary_op!(Add, add, Add);
+binary_op!(Sub, sub, Sub);
+binary_op!(Mul, mul, Mul);
+binary_op!(Div, div, Div);
+
+impl<T: Value> ops::Neg for PtrVWrap<T> {
+    type Output = PtrVWrap<T>;
+    fn neg(self) -> PtrVWrap<T> {
+        Neg(self)
+    }
+}
+
+impl<T: Value> ops::Neg for &PtrVWrap<T> {
+    type Output = PtrVWrap<T>;
+    fn neg(self) -> PtrVWrap<T> {
+        Neg(self.clone())
+    }
+}
+
+macro_rules! unary_method {
+    ($($method:ident => $func:ident),*) => {
+        $(
+            pub fn $method(&self) -> PtrVWrap<T> {
+                $func(self.clone())
+            }
+        )*
+    };
+}
+
+/// method forms of the free functions
+impl<T: Value> PtrVWrap<T> {
+    unary_method!(
+        sin => Sin, cos => Cos, tan => Tan, exp => Exp, ln => Ln, sqrt => Sqrt, abs => Abs,
+        tanh => Tanh, sinh => Sinh, cosh => Cosh, asin => Asin, acos => Acos, atan => Atan,
+        log10 => Log10, log2 => Log2, sigmoid => Sigmoid, softplus => Softplus, erf => Erf,
+        transpose => Transpose, sum => Sum, mean => Mean, stop_gradient => StopGradient
+    );
+
+    /// constant holding the current value, the graph behind it is not kept
+    pub fn detach(&self) -> PtrVWrap<T> {
+        Const(crate::grad::eval(std::slice::from_ref(self)).remove(0))
+    }
+
+    /// power with a constant exponent
+    pub fn powf(&self, expo: f64) -> PtrVWrap<T> {
+        Pow(self.clone(), Const(T::from_f64(expo)))
+    }
+
+    /// power with an exponent computed in the graph
+    pub fn pow(&self, expo: &PtrVWrap<T>) -> PtrVWrap<T> {
+        Pow(self.clone(), expo.clone())
+    }
+
+    /// four quadrant inverse tangent of self/other
+    pub fn atan2(&self, other: &PtrVWrap<T>) -> PtrVWrap<T> {
+        Atan2(self.clone(), other.clone())
+    }
+
+    pub fn max(&self, other: &PtrVWrap<T>) -> PtrVWrap<T> {
+        Max(self.clone(), other.clone())
+    }
+
+    pub fn min(&self, other: &PtrVWrap<T>) -> PtrVWrap<T> {
+        Min(self.clone(), other.clone())
+    }
+
+    pub fn matmul(&self, other: &PtrVWrap<T>) -> PtrVWrap<T> {
+        MatMul(self.clone(), other.clone())
+    }
+}
+
+#[test]
+fn test_operators_match_free_functions() {
+    let x = Leaf(3.).active();
+    let y = Leaf(2.);
+
+    //f=(x+y)*x-x/y, df/dx=2x+y-1/y, df/dy=x-(-x/y^2)
+    let mut f = (&x + &y) * &x - &x / &y;
+    let mut g = Minus(
+        Mul(Add(x.clone(), y.clone()), x.clone()),
+        Div(x.clone(), y.clone()),
+    );
+    assert_eq!(f.apply_fwd(), g.apply_fwd());
+    assert!((f.apply_fwd() - 13.5_f64).abs() < 1e-12);
+
+    let adj_f = f.rev();
+    let adj_g = g.rev();
+    for l in &[&x, &y] {
+        assert_eq!(adj_f[*l].clone().apply_rev(), adj_g[*l].clone().apply_rev());
+    }
+    assert!((adj_f[&x].clone().apply_rev() - 7.5).abs() < 1e-12);
+    assert!((adj_f[&y].clone().apply_rev() - 3.75).abs() < 1e-12);
+
+    assert!((f.fwd().apply_fwd() - 7.5).abs() < 1e-12);
+
+    let mut n = -&x * -y.clone();
+    assert!((n.apply_fwd() - 6.).abs() < 1e-12);
+}
+
+#[test]
+fn test_scalar_operands() {
+    use crate::valtype::ValType;
+
+    let x = Leaf(2_f64).active();
+
+    //f=2x^2+1/x-3, f'=4x-1/x^2
+    let mut f = 2. * x.powf(2.0) + 1 / &x - 3;
+    assert!((f.apply_fwd() - 5.5).abs() < 1e-12);
+    assert!((f.fwd().apply_fwd() - 7.75).abs() < 1e-12);
+    assert!((f.rev()[&x].clone().apply_rev() - 7.75).abs() < 1e-12);
+
+    //integer scalars are weakly typed, float scalars are f64
+    let z = Leaf(ValType::F(2.)).active();
+    match ((&z * 3 + 1) / 2).apply_fwd() {
+        ValType::F(v) => assert!((v - 3.5).abs() < 1e-6),
+        v => panic!("expected f32, found {:?}", v),
+    }
+    match (&z * 1.5).apply_fwd() {
+        ValType::D(v) => assert!((v - 3.).abs() < 1e-12),
+        v => panic!("expected f64, found {:?}", v),
+    }
+    match (z.sin() * &z).exp().fwd().apply_fwd() {
+        ValType::F(_) => {}
+        v => panic!("expected f32, found {:?}", v),
+    }
+}
@@ -0,0 +1,254 @@
+//! Property tests on randomly composed expressions
+//!
+//! derivatives from scalar and vector forward mode, symbolic and numeric reverse
+//! mode and central finite differences must agree for every input
+
+use crate::core::*;
+use crate::grad::{grad_with, Mode};
+use crate::jacobian::{hvp, jacobian_fwd, jvp};
+use crate::taylor::taylor;
+use ndarray::Array1;
+use ndarray_rand::RandomExt;
+use rand::distributions::Uniform;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+struct ExprGen {
+    rng: StdRng,
+    leaves: Vec<PtrVWrap<f64>>,
+    /// previously built subexpressions, reused to create shared subgraphs
+    pool: Vec<PtrVWrap<f64>>,
+}
+
+impl ExprGen {
+    fn new(seed: u64, num_leaves: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let x = Array1::random_using(num_leaves, Uniform::new(-1., 1.), &mut rng);
+        ExprGen {
+            rng,
+            leaves: x.iter().map(|v| Leaf(*v)).collect(),
+            pool: vec![],
+        }
+    }
+
+    /// value >= 1
+    fn positive(&mut self, e: PtrVWrap<f64>) -> PtrVWrap<f64> {
+        Add(Mul(e.clone(), e), Const(1.))
+    }
+
+    fn expr(&mut self, depth: usize) -> PtrVWrap<f64> {
+        if depth == 0 {
+            return match self.rng.gen_range(0, 4) {
+                0 => Const(self.rng.gen_range(-2., 2.)),
+                1 if !self.pool.is_empty() => {
+                    let i = self.rng.gen_range(0, self.pool.len());
+                    self.pool[i].clone()
+                }
+                _ => {
+                    let i = self.rng.gen_range(0, self.leaves.len());
+                    self.leaves[i].clone()
+                }
+            };
+        }
+        let a = self.expr(depth - 1);
+        let e = match self.rng.gen_range(0, 28) {
+            0 => Add(a, self.expr(depth - 1)),
+            1 => Minus(a, self.expr(depth - 1)),
+            2 => Mul(a, self.expr(depth - 1)),
+            3 => {
+                let b = self.expr(depth - 1);
+                Div(a, self.positive(b))
+            }
+            4 => Sin(a),
+            5 => Cos(a),
+            6 => Tan(Mul(Const(0.5), Sin(a))),
+            7 => Exp(Sin(a)),
+            8 => Ln(self.positive(a)),
+            9 => {
+                let b = self.expr(depth - 1);
+                Pow(self.positive(Sin(a)), Sin(b))
+            }
+            10 => Sqrt(self.positive(a)),
+            11 => Abs(a),
+            12 => Neg(a),
+            13 => Sub(a, self.expr(depth - 1)),
+            14 => Tanh(a),
+            15 => Sinh(Sin(a)),
+            16 => Cosh(Sin(a)),
+            17 => Asin(Mul(Const(0.5), Sin(a))),
+            18 => Acos(Mul(Const(0.5), Sin(a))),
+            19 => Atan(a),
+            20 => {
+                let b = self.expr(depth - 1);
+                Atan2(a, self.positive(b))
+            }
+            21 => Log10(self.positive(a)),
+            22 => Log2(self.positive(a)),
+            23 => Sigmoid(a),
+            24 => Softplus(a),
+            25 => Erf(a),
+            26 => Max(a, self.expr(depth - 1)),
+            _ => Min(a, self.expr(depth - 1)),
+        };
+        self.pool.push(e.clone());
+        e
+    }
+}
+
+fn close(a: f64, b: f64, tol: f64) -> bool {
+    (a - b).abs() <= tol * (1. + a.abs().max(b.abs()))
+}
+
+#[test]
+fn test_random_expressions() {
+    for seed in 0..200 {
+        let mut gen = ExprGen::new(seed, 3);
+        let depth = 1 + (seed as usize % 4);
+        let mut f = gen.expr(depth);
+        let mut leaves = gen.leaves.clone();
+
+        let adjoints = f.rev();
+        let mut tangent = f.fwd();
+        let mut tangent_simplified = tangent.simplify();
+        let numeric = grad_with(&f, &leaves, Mode::Numeric);
+        let vector = jacobian_fwd(std::slice::from_ref(&f), &leaves);
+
+        for i in 0..leaves.len() {
+            for (j, l) in leaves.iter_mut().enumerate() {
+                if i == j {
+                    l.active();
+                } else {
+                    l.inactive();
+                }
+            }
+
+            let d_fwd = tangent.apply_fwd();
+            assert!(close(tangent_simplified.apply_fwd(), d_fwd, 1e-12));
+            let d_rev = adjoints
+                .get(&leaves[i])
+                .map_or(0., |a| a.clone().apply_rev());
+
+            let x: f64 = leaves[i].0.borrow().val.clone().unwrap();
+            let h = 1e-6;
+            leaves[i].set_val(x + h);
+            let f_hi = f.apply_fwd();
+            leaves[i].set_val(x - h);
+            let f_lo = f.apply_fwd();
+            leaves[i].set_val(x);
+            let d_fd = (f_hi - f_lo) / (2. * h);
+
+            assert!(
+                close(d_fwd, d_rev, 1e-9),
+                "seed {}: fwd {} rev {}",
+                seed,
+                d_fwd,
+                d_rev
+            );
+            assert!(
+                close(numeric[i], d_rev, 1e-9),
+                "seed {}: numeric rev {} rev {}",
+                seed,
+                numeric[i],
+                d_rev
+            );
+            assert!(
+                close(vector[[0, i]], d_fwd, 1e-9),
+                "seed {}: vector fwd {} fwd {}",
+                seed,
+                vector[[0, i]],
+                d_fwd
+            );
+            assert!(
+                close(d_fwd, d_fd, 1e-5),
+                "seed {}: fwd {} finite difference {}",
+                seed,
+                d_fwd,
+                d_fd
+            );
+        }
+    }
+}
+
+#[test]
+fn test_random_expressions_2nd_order() {
+    //fwd-over-rev and rev-over-rev agree with finite differences of the gradient
+    for seed in 0..100 {
+        let mut gen = ExprGen::new(1000 + seed, 2);
+        let depth = 1 + (seed as usize % 3);
+        let f = gen.expr(depth);
+        let mut leaves = gen.leaves.clone();
+
+        let mut g = match f.rev().get(&leaves[0]) {
+            Some(g) => g.clone(),
+            None => continue,
+        };
+
+        leaves[0].active();
+        leaves[1].inactive();
+
+        let dd_fwd = g.fwd().apply_fwd();
+        let dd_rev = g
+            .rev()
+            .get(&leaves[0])
+            .map_or(0., |a| a.clone().apply_rev());
+
+        let x: f64 = leaves[0].0.borrow().val.clone().unwrap();
+        let h = 1e-5;
+        leaves[0].set_val(x + h);
+        let g_hi = g.apply_rev();
+        leaves[0].set_val(x - h);
+        let g_lo = g.apply_rev();
+        leaves[0].set_val(x);
+        let dd_fd = (g_hi - g_lo) / (2. * h);
+
+        assert!(
+            close(dd_fwd, dd_rev, 1e-9),
+            "seed {}: fwd-over-rev {} rev-over-rev {}",
+            seed,
+            dd_fwd,
+            dd_rev
+        );
+        assert!(
+            close(dd_fwd, dd_fd, 1e-4),
+            "seed {}: fwd-over-rev {} finite difference {}",
+            seed,
+            dd_fwd,
+            dd_fd
+        );
+    }
+}
+
+#[test]
+fn test_random_expressions_taylor() {
+    //taylor coefficients along a direction agree with jvp and hvp
+    for seed in 0..100 {
+        let mut gen = ExprGen::new(2000 + seed, 3);
+        let depth = 1 + (seed as usize % 4);
+        let f = gen.expr(depth);
+        let leaves = gen.leaves.clone();
+        let v: Vec<f64> = (0..leaves.len())
+            .map(|_| gen.rng.gen_range(-1., 1.))
+            .collect();
+
+        let coeffs: Vec<Vec<f64>> = v.iter().map(|v| vec![*v]).collect();
+        let s = taylor(std::slice::from_ref(&f), &leaves, &coeffs, 2);
+        let d1 = jvp(std::slice::from_ref(&f), &leaves, &v)[0];
+        let hv = hvp(&f, &leaves, &v);
+        let d2: f64 = hv.iter().zip(v.iter()).map(|(h, v)| h * v).sum();
+
+        assert!(
+            close(s[0][1], d1, 1e-9),
+            "seed {}: taylor {} jvp {}",
+            seed,
+            s[0][1],
+            d1
+        );
+        assert!(
+            close(2. * s[0][2], d2, 1e-8),
+            "seed {}: taylor {} hvp {}",
+            seed,
+            2. * s[0][2],
+            d2
+        );
+    }
+}
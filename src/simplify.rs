@@ -0,0 +1,264 @@
+//! Simplification of derivative graphs
+//!
+//! Tangent and adjoint rules produce many nodes like `Add(x, 0)`, `Mul(x, 1)` or
+//! `SumLike(x, y)` where nothing is reduced. Removing them keeps nested derivatives
+//! small.
+
+use crate::core::*;
+use crate::grad::eval;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::rc::Rc;
+
+impl<T: Value> PtrVWrap<T> {
+    /// equivalent graph with constant subexpressions folded, identities such as
+    /// x+0, x*1 and x*0 removed and equal subexpressions merged
+    ///
+    /// leaves and constants are kept as they are, so tangents and adjoints of the
+    /// simplified graph refer to the same leaf nodes. The current values decide
+    /// which identities hold, so leaf values are assumed to keep their shapes.
+    #[allow(clippy::mutable_key_type)]
+    pub fn simplify(&self) -> PtrVWrap<T> {
+        eval(std::slice::from_ref(self));
+
+        let mut map: HashMap<PtrVWrap<T>, PtrVWrap<T>> = HashMap::new();
+        let mut cse: HashMap<(String, Vec<usize>), PtrVWrap<T>> = HashMap::new();
+
+        for n in topo_order(std::slice::from_ref(self)) {
+            let s = simplify_node(&n, &map, &mut cse);
+            map.insert(n, s);
+        }
+
+        map[self].clone()
+    }
+}
+
+#[allow(clippy::mutable_key_type)]
+fn simplify_node<T: Value>(
+    n: &PtrVWrap<T>,
+    map: &HashMap<PtrVWrap<T>, PtrVWrap<T>>,
+    cse: &mut HashMap<(String, Vec<usize>), PtrVWrap<T>>,
+) -> PtrVWrap<T> {
+    let (raw, inp, val) = {
+        let n = n.0.deref().borrow();
+        let val = n.val.clone().expect("node not evaluated");
+        (n.raw.clone(), n.inp.clone(), val)
+    };
+
+    if inp.is_empty() {
+        return n.clone();
+    }
+
+    let args: Vec<PtrVWrap<T>> = inp.iter().map(|i| map[i].clone()).collect();
+    let op = AsAny::as_any(&*raw);
+
+    //constant folding, ops that only depend on the shape of their inputs are constant too
+    let shape_only =
+        op.is::<OpZero>() || op.is::<OpOne>() || op.is::<OpScalarLike>() || op.is::<OpCount>();
+    if raw.pure() && (shape_only || args.iter().all(is_constant)) {
+        return Const(val);
+    }
+
+    if let Some(s) = identity(op, &args, &val) {
+        return s;
+    }
+
+    let s = if args.iter().zip(inp.iter()).all(|(a, i)| a == i) {
+        n.clone()
+    } else {
+        let s = VWrap::new_with_op(raw.clone(), args.clone());
+        s.0.deref().borrow_mut().val = Some(val);
+        s
+    };
+
+    //common subexpressions, operations are compared by their parameters
+    if raw.pure() {
+        let key = (
+            format!("{:?}", raw),
+            args.iter()
+                .map(|a| Rc::as_ptr(&a.0) as *const u8 as usize)
+                .collect(),
+        );
+        return cse.entry(key).or_insert(s).clone();
+    }
+    s
+}
+
+/// replacement for an operation with an identity element or absorbing element as input
+fn identity<T: Value>(
+    op: &dyn std::any::Any,
+    args: &[PtrVWrap<T>],
+    val: &T,
+) -> Option<PtrVWrap<T>> {
+    let shape = val.shape();
+    let same_shape = |x: &PtrVWrap<T>| value(x).shape() == shape;
+    let zero = |x: &PtrVWrap<T>| is_constant(x) && value(x).is_zero();
+    let one = |x: &PtrVWrap<T>| is_constant(x) && value(x).is_one();
+    let keep = |x: &PtrVWrap<T>| Some(x.clone());
+
+    if op.is::<OpAdd>() {
+        if zero(&args[1]) && same_shape(&args[0]) {
+            return keep(&args[0]);
+        }
+        if zero(&args[0]) && same_shape(&args[1]) {
+            return keep(&args[1]);
+        }
+    } else if op.is::<OpSub>() {
+        if zero(&args[1]) && same_shape(&args[0]) {
+            return keep(&args[0]);
+        }
+        if zero(&args[0]) && same_shape(&args[1]) {
+            //consumers compare shapes, so the value is filled in as for rebuilt nodes
+            let s = Neg(args[1].clone());
+            s.0.deref().borrow_mut().val = Some(val.clone());
+            return Some(s);
+        }
+    } else if op.is::<OpMul>() {
+        if zero(&args[0]) || zero(&args[1]) {
+            return Some(Const(val.zeros_like()));
+        }
+        if one(&args[1]) && same_shape(&args[0]) {
+            return keep(&args[0]);
+        }
+        if one(&args[0]) && same_shape(&args[1]) {
+            return keep(&args[1]);
+        }
+    } else if op.is::<OpDiv>() {
+        if zero(&args[0]) {
+            return Some(Const(val.zeros_like()));
+        }
+        if one(&args[1]) && same_shape(&args[0]) {
+            return keep(&args[0]);
+        }
+    } else if op.is::<OpPow>() {
+        if zero(&args[1]) {
+            return Some(Const(val.ones_like()));
+        }
+        if one(&args[1]) && same_shape(&args[0]) {
+            return keep(&args[0]);
+        }
+    } else if op.is::<OpNeg>() {
+        //-(-x) = x
+        let inner = args[0].0.deref().borrow();
+        if AsAny::as_any(&*inner.raw).is::<OpNeg>() {
+            return keep(&inner.inp[0]);
+        }
+    } else if op.is::<OpSumLike>() || op.is::<OpBroadcastLike>() || op.is::<OpReshapeLike>() {
+        //nothing to reduce, broadcast or reshape
+        if same_shape(&args[0]) {
+            return keep(&args[0]);
+        }
+    }
+    None
+}
+
+fn is_constant<T: Value>(x: &PtrVWrap<T>) -> bool {
+    let x = x.0.deref().borrow();
+    let op = AsAny::as_any(&*x.raw);
+    op.is::<OpConst>() || (x.inp.is_empty() && (op.is::<OpZero>() || op.is::<OpOne>()))
+}
+
+fn value<T: Value>(x: &PtrVWrap<T>) -> T {
+    x.0.deref()
+        .borrow()
+        .val
+        .clone()
+        .expect("node not evaluated")
+}
+
+#[cfg(test)]
+fn count_nodes<T: Value>(x: &PtrVWrap<T>) -> usize {
+    topo_order(std::slice::from_ref(x)).len()
+}
+
+#[test]
+fn test_simplify_nested_fwd() {
+    let mut x = Leaf(0.7_f64).active();
+    let f = Mul(Sin(x.clone()), x.clone());
+
+    let mut d3 = f.fwd().fwd().fwd();
+    let mut s = d3.simplify();
+    assert!(count_nodes(&s) < count_nodes(&d3) / 2);
+
+    for v in &[0.7, -1.3, 2.] {
+        x.set_val(*v);
+        assert!((s.apply_fwd() - d3.apply_fwd()).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_simplify_keeps_leaves() {
+    let x = Leaf(0.7_f64).active();
+    let y = Leaf(1.1_f64);
+    let f = Mul(Exp(Mul(x.clone(), y.clone())), x.clone());
+
+    let adj = f.rev();
+    let mut g = adj[&x].simplify();
+    assert!((g.apply_rev() - adj[&x].clone().apply_rev()).abs() < 1e-12);
+
+    //adjoints of the simplified graph are still found by leaf
+    let adj2 = g.rev();
+    let mut h = adj2[&y].simplify();
+    let expected = adj[&x].rev()[&y].clone().apply_rev();
+    assert!((h.apply_rev() - expected).abs() < 1e-12);
+
+    //equal leaves are never merged
+    let a = Leaf(1_f64);
+    let b = Leaf(1_f64);
+    assert_eq!(count_nodes(&Add(a, b).simplify()), 3);
+}
+
+#[test]
+fn test_simplify_cse_and_folding() {
+    let x = Leaf(0.3_f64);
+
+    //sin(x) is shared, 2*3 is folded
+    let f = Add(
+        Mul(Sin(x.clone()), Mul(Const(2.), Const(3.))),
+        Sin(x.clone()),
+    );
+    let s = f.simplify();
+    assert_eq!(count_nodes(&s), 5);
+    assert!((s.clone().apply_fwd() - 7. * 0.3_f64.sin()).abs() < 1e-12);
+}
+
+#[test]
+fn test_simplify_keeps_broadcast() {
+    use crate::valtype::ValType;
+    use ndarray::arr1;
+
+    let x = Leaf(ValType::D(2.));
+    let t = Leaf(ValType::from(arr1(&[1., 2., 3.]).into_dyn()));
+
+    //adding a zero tensor broadcasts x and must not be removed
+    let mut f = Add(x.clone(), Mul(Const(ValType::I(0)), t.clone()));
+    let mut s = f.simplify();
+    assert_eq!(s.apply_fwd().shape(), vec![3]);
+    assert_eq!(f.apply_fwd().shape(), vec![3]);
+
+    //scalar identities are removed
+    let g = Mul(Add(x.clone(), Const(ValType::I(0))), Const(ValType::I(1)));
+    assert!(g.simplify() == x);
+}
+
+#[test]
+fn test_simplify_sub_from_zero() {
+    //0-sin(x) becomes a negation that is simplified further as an input
+    let x = Leaf(0.7_f64);
+    let mut f = Add(Sub(Const(0.), Sin(x.clone())), Const(0.));
+    let mut s = f.simplify();
+    assert!((s.apply_fwd() + 0.7_f64.sin()).abs() < 1e-12);
+    assert!((s.apply_fwd() - f.apply_fwd()).abs() < 1e-12);
+}
+
+#[test]
+fn test_simplify_sub_from_zero_in_derivative() {
+    //tangent of the adjoint contains 0-x
+    let x = Leaf(0.7_f64).active();
+    let f = Mul(Sub(Const(1.), x.clone()), x.clone());
+    let mut d = f.rev()[&x].fwd();
+    let mut s = d.simplify();
+    assert!((s.apply_fwd() + 2.).abs() < 1e-12);
+    assert!((s.apply_fwd() - d.apply_fwd()).abs() < 1e-12);
+}
@@ -0,0 +1,189 @@
+//! Numeric reverse mode
+//!
+//! A Wengert list records every operation of a forward evaluation together with
+//! its value. Back-propagation then works on plain values through
+//! FWrap::adjoint_val instead of building adjoint graphs, which is the cheaper
+//! choice when only first order gradients are needed.
+
+use crate::core::{topo_order, PtrVWrap};
+use crate::valtype::ValType;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// Wengert list of an evaluated graph
+pub struct Tape<T: Value = ValType> {
+    /// operations in evaluation order, the output is last
+    nodes: Vec<PtrVWrap<T>>,
+    /// values computed for nodes
+    vals: Vec<T>,
+    /// positions of the inputs of each node
+    inputs: Vec<Vec<usize>>,
+    index: HashMap<PtrVWrap<T>, usize>,
+}
+
+impl<T: Value> Tape<T> {
+    /// evaluates output and records each operation
+    #[allow(clippy::mutable_key_type)]
+    pub fn record(output: &PtrVWrap<T>) -> Self {
+        let nodes = topo_order(std::slice::from_ref(output));
+        let mut index = HashMap::new();
+        let mut vals: Vec<T> = vec![];
+        let mut inputs = vec![];
+
+        for (k, n) in nodes.iter().enumerate() {
+            let inp: Vec<usize> = n.0.deref().borrow().inp.iter().map(|i| index[i]).collect();
+
+            let v = match n.cached() {
+                Some(v) => v,
+                None => {
+                    let args: Vec<(T, Option<T>)> =
+                        n.0.deref()
+                            .borrow()
+                            .inp
+                            .iter()
+                            .zip(inp.iter())
+                            .map(|(i, &idx)| {
+                                let v = vals[idx].clone();
+                                let s = i.0.deref().borrow().seed_arg(&v);
+                                (v, s)
+                            })
+                            .collect();
+                    n.compute(args)
+                }
+            };
+
+            vals.push(v);
+            inputs.push(inp);
+            index.insert(n.clone(), k);
+        }
+
+        Tape {
+            nodes,
+            vals,
+            inputs,
+            index,
+        }
+    }
+
+    /// value of the recorded output
+    pub fn value(&self) -> &T {
+        self.vals.last().expect("empty tape")
+    }
+
+    /// number of recorded operations
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// back-propagates adjoint values from the output, seeded with 1,
+    /// and returns the adjoints of wrt, zero for nodes not on the tape
+    pub fn gradient(&self, wrt: &[PtrVWrap<T>]) -> Vec<T> {
+        let mut adj: Vec<Option<T>> = vec![None; self.nodes.len()];
+        let last = self.nodes.len() - 1;
+        adj[last] = Some(self.vals[last].ones_like());
+
+        for k in (0..self.nodes.len()).rev() {
+            if self.inputs[k].is_empty() {
+                continue;
+            }
+            let a = match &adj[k] {
+                Some(a) => a.clone(),
+                None => continue,
+            };
+
+            let inp_vals: Vec<T> = self.inputs[k]
+                .iter()
+                .map(|&i| self.vals[i].clone())
+                .collect();
+            let raw = self.nodes[k].0.deref().borrow().raw.clone();
+            let contribs = raw.adjoint_val(&inp_vals, &self.vals[k], &a);
+
+            assert_eq!(contribs.len(), self.inputs[k].len());
+
+            for (j, (&i, c)) in self.inputs[k].iter().zip(contribs).enumerate() {
+                if raw.stops_gradient(j) {
+                    continue;
+                }
+                adj[i] = Some(match adj[i].take() {
+                    Some(acc) => acc.add(&c),
+                    None => c,
+                });
+            }
+        }
+
+        wrt.iter()
+            .map(|w| match self.index.get(w) {
+                Some(&k) => adj[k].clone().unwrap_or_else(|| self.vals[k].zeros_like()),
+                None => match &w.0.deref().borrow().val {
+                    Some(v) => v.zeros_like(),
+                    None => T::from_i32(0),
+                },
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_tape_matches_symbolic() {
+    use crate::core::*;
+    use crate::grad::{grad_with, Mode};
+
+    let x = Leaf(0.7_f64);
+    let y = Leaf(1.3_f64);
+    let z = Leaf(5_f64);
+
+    let s = (&x * &y).sin();
+    let f = Pow(&s + 2, y.clone()) / x.exp() + s.tanh() * Atan2(x.clone(), y.clone())
+        - x.sqrt().sigmoid()
+        + Max(x.clone(), s.clone())
+        + Min(Tan(s.clone()), Abs(Sinh(y.clone())))
+        + Asin(s.clone()) * Acos(Neg(s.clone()))
+        + Atan(Cosh(x.clone())) / Log10(Add(y.clone(), Const(1.)))
+        + Log2(x.clone()) * Softplus(s.clone())
+        + Erf(y.clone() * 0.1);
+
+    let ws = [x.clone(), y.clone(), z.clone()];
+    let numeric = grad_with(&f, &ws, Mode::Numeric);
+    let symbolic = grad_with(&f, &ws, Mode::Symbolic);
+    for (n, s) in numeric.iter().zip(symbolic.iter()) {
+        assert!((n - s).abs() < 1e-12, "{} {}", n, s);
+    }
+    assert_eq!(numeric[2], 0.);
+
+    let tape = Tape::record(&f);
+    assert!((tape.value() - f.clone().apply_fwd()).abs() < 1e-12);
+}
+
+#[test]
+fn test_tape_tensor() {
+    use crate::core::*;
+    use ndarray::{arr1, arr2};
+
+    //l=mean((A w - b)^2)
+    let a = Leaf(ValType::from(
+        arr2(&[[1., 2.], [3., 4.], [5., 6.]]).into_dyn(),
+    ));
+    let w = Leaf(ValType::from(arr2(&[[0.5], [-1.]]).into_dyn()));
+    let b = Leaf(ValType::from(arr1(&[1., 0., 2.]).into_dyn()));
+    let r = MatMul(a.clone(), w.clone()) - Reshape(b.clone(), &[3, 1]);
+    //row sums broadcast against the column of residuals
+    let s = SumAxis(Pow(a.clone(), Const(ValType::D(2.))), 1);
+    let l = Mean(&r * &r) + Sum(MeanAxis(s * &r, 0));
+
+    let tape = Tape::record(&l);
+    let g = tape.gradient(&[a.clone(), w.clone(), b.clone()]);
+    let adj = l.rev();
+    for (i, v) in [a, w, b].iter().enumerate() {
+        let expected = adj[v].clone().apply_rev().to_array();
+        let got = g[i].to_array();
+        assert_eq!(expected.shape(), got.shape());
+        for (e, g) in expected.iter().zip(got.iter()) {
+            assert!((e - g).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,516 @@
+//! Univariate Taylor mode
+//!
+//! Every node carries the truncated Taylor coefficients x_0..x_k of its value
+//! along a curve x(t), x_0 being the value itself. One sweep through
+//! FWrap::taylor_val gives k-th derivatives d^k/dt^k = k! x_k without building
+//! nested tangent graphs. The rules below work coefficient by coefficient, so
+//! elementwise ops also apply to tensors.
+//!
+//! Ops without a rule fall back to repeated tangents of the op. Each order
+//! nests fwd() once more, so the tangent graphs and the cost grow
+//! combinatorially with the order. Custom ops avoid this by implementing
+//! Operation::taylor_val or GradientOverride::taylor_val.
+
+use crate::core::{topo_order, Add, Const, FWrap, Leaf, Mul, PtrVWrap, VWrap};
+use crate::grad::eval;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// Taylor coefficients of the outputs up to the given order
+///
+/// the inputs move along x_i(t) = x_i + coeffs[i][0] t + coeffs[i][1] t^2 + ..,
+/// missing coefficients are zero. Nodes not depending on any input have
+/// constant expansions.
+#[allow(clippy::mutable_key_type)]
+pub fn taylor<T: Value>(
+    outputs: &[PtrVWrap<T>],
+    inputs: &[PtrVWrap<T>],
+    coeffs: &[Vec<T>],
+    order: usize,
+) -> Vec<Vec<T>> {
+    assert_eq!(
+        inputs.len(),
+        coeffs.len(),
+        "one set of coefficients per input"
+    );
+    let seeded: HashMap<&PtrVWrap<T>, &Vec<T>> = inputs.iter().zip(coeffs).collect();
+
+    let order_nodes = topo_order(outputs);
+    let vals = eval(&order_nodes);
+    let index: HashMap<PtrVWrap<T>, usize> = order_nodes
+        .iter()
+        .enumerate()
+        .map(|(k, n)| (n.clone(), k))
+        .collect();
+
+    //None for nodes not depending on any input
+    let mut series: Vec<Option<Vec<T>>> = Vec::with_capacity(order_nodes.len());
+    for (k, n) in order_nodes.iter().enumerate() {
+        if let Some(c) = seeded.get(n) {
+            let mut s = vec![vals[k].clone()];
+            s.extend((0..order).map(|j| match c.get(j) {
+                Some(c) => c.clone(),
+                None => vals[k].zeros_like(),
+            }));
+            series.push(Some(s));
+            continue;
+        }
+
+        let inp: Vec<usize> = n.0.deref().borrow().inp.iter().map(|i| index[i]).collect();
+        if inp.iter().all(|&i| series[i].is_none()) {
+            series.push(None);
+            continue;
+        }
+
+        let inp_series: Vec<Vec<T>> = inp
+            .iter()
+            .map(|&i| match &series[i] {
+                Some(s) => s.clone(),
+                None => constant(&vals[i], order + 1),
+            })
+            .collect();
+        let raw = n.0.deref().borrow().raw.clone();
+        let s = raw
+            .taylor_val(&inp_series, &vals[k])
+            .unwrap_or_else(|| from_tangents(&raw, &inp_series));
+        series.push(Some(s));
+    }
+
+    outputs
+        .iter()
+        .map(|o| {
+            let k = index[o];
+            match &series[k] {
+                Some(s) => s.clone(),
+                None => constant(&vals[k], order + 1),
+            }
+        })
+        .collect()
+}
+
+/// coefficients of an op without Taylor rule from repeated tangents of the op
+/// applied to the input polynomials in t at t=0
+fn from_tangents<T: Value>(raw: &Rc<dyn FWrap<T>>, inputs: &[Vec<T>]) -> Vec<T> {
+    let n = inputs[0].len();
+    let mut t = Leaf(T::from_i32(0));
+    t.active();
+
+    //Horner form of each input polynomial
+    let inp = inputs
+        .iter()
+        .map(|c| {
+            let mut p = Const(c[n - 1].clone());
+            for j in (0..n - 1).rev() {
+                p = Add(Const(c[j].clone()), Mul(t.clone(), p));
+            }
+            p
+        })
+        .collect();
+
+    let mut d = VWrap::new_with_op(raw.clone(), inp);
+    let mut res = vec![];
+    for j in 0..n {
+        if j > 0 {
+            d = d.fwd();
+        }
+        res.push(div_factorial(&eval(std::slice::from_ref(&d))[0], j));
+    }
+    res
+}
+
+/// c/j!, divided one factor at a time since j! overflows i32 from j=13
+pub(crate) fn div_factorial<T: Value>(c: &T, j: usize) -> T {
+    (2..=j).fold(c.clone(), |acc, k| acc.div(&T::from_i32(k as i32)))
+}
+
+/// expansion of a value not depending on t
+pub(crate) fn constant<T: Value>(v: &T, n: usize) -> Vec<T> {
+    let mut s = vec![v.clone()];
+    s.extend((1..n).map(|_| v.zeros_like()));
+    s
+}
+
+pub(crate) fn add<T: Value>(a: &[T], b: &[T]) -> Vec<T> {
+    a.iter().zip(b).map(|(a, b)| a.add(b)).collect()
+}
+
+pub(crate) fn sub<T: Value>(a: &[T], b: &[T]) -> Vec<T> {
+    a.iter().zip(b).map(|(a, b)| a.sub(b)).collect()
+}
+
+pub(crate) fn neg<T: Value>(a: &[T]) -> Vec<T> {
+    a.iter().map(|a| a.neg()).collect()
+}
+
+pub(crate) fn scale<T: Value>(a: &[T], s: &T) -> Vec<T> {
+    a.iter().map(|a| a.mul(s)).collect()
+}
+
+/// Cauchy product c_j = sum a_i b_(j-i) under the given multiplication
+pub(crate) fn convolve<T: Value>(a: &[T], b: &[T], mul: impl Fn(&T, &T) -> T) -> Vec<T> {
+    (0..a.len().min(b.len()))
+        .map(|j| (1..=j).fold(mul(&a[0], &b[j]), |acc, i| acc.add(&mul(&a[i], &b[j - i]))))
+        .collect()
+}
+
+pub(crate) fn mul<T: Value>(a: &[T], b: &[T]) -> Vec<T> {
+    convolve(a, b, |a, b| a.mul(b))
+}
+
+/// c = a/b from a = b c solved for c_j
+pub(crate) fn div<T: Value>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut c: Vec<T> = vec![];
+    for j in 0..a.len().min(b.len()) {
+        let s = (1..=j).fold(a[j].clone(), |acc, i| acc.sub(&b[i].mul(&c[j - i])));
+        c.push(s.div(&b[0]));
+    }
+    c
+}
+
+/// coefficients of x'(t), one fewer than x
+pub(crate) fn deriv<T: Value>(x: &[T]) -> Vec<T> {
+    (1..x.len())
+        .map(|j| x[j].mul(&T::from_i32(j as i32)))
+        .collect()
+}
+
+/// coefficients of y with y(0) = y0 and y'(t) = d(t), one more than d
+pub(crate) fn integrate<T: Value>(y0: &T, d: &[T]) -> Vec<T> {
+    let mut y = vec![y0.clone()];
+    y.extend(
+        d.iter()
+            .enumerate()
+            .map(|(m, d)| d.div(&T::from_i32(m as i32 + 1))),
+    );
+    y
+}
+
+/// coefficient i of y^2 given y_0..y_i
+pub(crate) fn sq_coef<T: Value>(y: &[T], i: usize) -> T {
+    (1..=i).fold(y[0].mul(&y[i]), |acc, l| acc.add(&y[l].mul(&y[i - l])))
+}
+
+/// y with y(0) = y0 and y' = w(y) x', w(y, i) gives coefficient i of w(y)
+/// from y_0..y_i
+pub(crate) fn riccati<T: Value>(x: &[T], y0: &T, w: impl Fn(&[T], usize) -> T) -> Vec<T> {
+    let dx = deriv(x);
+    let mut y = vec![y0.clone()];
+    let mut ws: Vec<T> = vec![];
+    for m in 0..dx.len() {
+        ws.push(w(&y, m));
+        let s = (1..=m).fold(ws[0].mul(&dx[m]), |acc, i| acc.add(&ws[i].mul(&dx[m - i])));
+        y.push(s.div(&T::from_i32(m as i32 + 1)));
+    }
+    y
+}
+
+pub(crate) fn exp<T: Value>(x: &[T], y0: &T) -> Vec<T> {
+    riccati(x, y0, |y, i| y[i].clone())
+}
+
+pub(crate) fn ln<T: Value>(x: &[T], y0: &T) -> Vec<T> {
+    integrate(y0, &div(&deriv(x), &x[..x.len() - 1]))
+}
+
+pub(crate) fn sigmoid<T: Value>(x: &[T], y0: &T) -> Vec<T> {
+    //y' = y(1-y) x'
+    riccati(x, y0, |y, i| y[i].sub(&sq_coef(y, i)))
+}
+
+/// sine and cosine, or their hyperbolic versions, from s' = c x', c' = -+s x'
+pub(crate) fn sin_cos<T: Value>(x: &[T], s0: &T, c0: &T, hyperbolic: bool) -> (Vec<T>, Vec<T>) {
+    let dx = deriv(x);
+    let mut s = vec![s0.clone()];
+    let mut c = vec![c0.clone()];
+    for m in 0..dx.len() {
+        let k = T::from_i32(m as i32 + 1);
+        let ds = (1..=m).fold(c[0].mul(&dx[m]), |acc, i| acc.add(&c[i].mul(&dx[m - i])));
+        let dc = (1..=m).fold(s[0].mul(&dx[m]), |acc, i| acc.add(&s[i].mul(&dx[m - i])));
+        s.push(ds.div(&k));
+        c.push(if hyperbolic {
+            dc.div(&k)
+        } else {
+            dc.neg().div(&k)
+        });
+    }
+    (s, c)
+}
+
+/// y = sqrt(x) from x = y^2 solved for y_j
+pub(crate) fn sqrt<T: Value>(x: &[T], y0: &T) -> Vec<T> {
+    let two_y0 = y0.mul(&T::from_i32(2));
+    let mut y = vec![y0.clone()];
+    for j in 1..x.len() {
+        let s = (1..j).fold(x[j].clone(), |acc, i| acc.sub(&y[i].mul(&y[j - i])));
+        y.push(s.div(&two_y0));
+    }
+    y
+}
+
+/// sqrt(1-x^2) as used by asin and acos
+pub(crate) fn one_minus_sq_sqrt<T: Value>(x: &[T]) -> Vec<T> {
+    let mut u = neg(&mul(x, x));
+    u[0] = T::from_i32(1).add(&u[0]);
+    let r0 = u[0].sqrt();
+    sqrt(&u, &r0)
+}
+
+/// y = a^r for constant r from a y' = r y a'
+pub(crate) fn powc<T: Value>(a: &[T], r: &T, y0: &T) -> Vec<T> {
+    let mut y = vec![y0.clone()];
+    for j in 1..a.len() {
+        let s = (1..=j)
+            .map(|i| {
+                let f = r
+                    .mul(&T::from_i32(i as i32))
+                    .sub(&T::from_i32((j - i) as i32));
+                f.mul(&a[i]).mul(&y[j - i])
+            })
+            .reduce(|acc, t| acc.add(&t))
+            .expect("empty sum");
+        y.push(s.div(&a[0].mul(&T::from_i32(j as i32))));
+    }
+    y
+}
+
+/// x_0 for mask m, y_0 for 1-m, used by max and min
+pub(crate) fn select<T: Value>(x: &[T], y: &[T], m: &T, y0: &T) -> Vec<T> {
+    let one_minus = T::from_i32(1).sub(m);
+    let mut s = vec![y0.clone()];
+    s.extend(
+        x.iter()
+            .zip(y)
+            .skip(1)
+            .map(|(x, y)| m.mul(x).add(&one_minus.mul(y))),
+    );
+    s
+}
+
+/// value function of an op as returned by FWrap::f
+pub(crate) type ValFn<T> = Box<dyn FnMut(Vec<(T, Option<T>)>, Option<T>) -> T>;
+
+/// applies a linear op to every coefficient of the first input,
+/// further inputs only contribute their value
+pub(crate) fn linear<T: Value>(mut f: ValFn<T>, inputs: &[Vec<T>], y0: &T) -> Vec<T> {
+    let mut s = vec![y0.clone()];
+    for j in 1..inputs[0].len() {
+        let args = inputs
+            .iter()
+            .enumerate()
+            .map(|(k, c)| (if k == 0 { c[j].clone() } else { c[0].clone() }, None))
+            .collect();
+        s.push(f(args, None));
+    }
+    s
+}
+
+#[cfg(test)]
+fn factorial(k: usize) -> f64 {
+    (1..=k).map(|i| i as f64).product()
+}
+
+#[test]
+fn test_taylor_elementary() {
+    use crate::core::*;
+
+    let x0 = 0.4_f64;
+    let x = Leaf(x0);
+    let order = 6;
+
+    //along x(t) = x0 + t the coefficients are f^(k)(x0)/k!
+    let outputs = vec![
+        Exp(x.clone()),
+        Sin(x.clone()),
+        Cos(x.clone()),
+        Ln(x.clone()),
+        Sqrt(x.clone()),
+        Pow(x.clone(), Const(2.5)),
+        Div(Const(1.), Add(Const(1.), x.clone())),
+    ];
+    let expected: Vec<Box<dyn Fn(usize) -> f64>> = vec![
+        Box::new(|_| x0.exp()),
+        Box::new(|k| (x0 + k as f64 * std::f64::consts::FRAC_PI_2).sin()),
+        Box::new(|k| (x0 + k as f64 * std::f64::consts::FRAC_PI_2).cos()),
+        Box::new(|k| {
+            if k == 0 {
+                x0.ln()
+            } else {
+                (-1_f64).powi(k as i32 + 1) * factorial(k - 1) / x0.powi(k as i32)
+            }
+        }),
+        Box::new(|k| (0..k).map(|i| 0.5 - i as f64).product::<f64>() * x0.powf(0.5 - k as f64)),
+        Box::new(|k| (0..k).map(|i| 2.5 - i as f64).product::<f64>() * x0.powf(2.5 - k as f64)),
+        Box::new(|k| (-1_f64).powi(k as i32) * factorial(k) / (1. + x0).powi(k as i32 + 1)),
+    ];
+
+    let s = taylor(&outputs, &[x], &[vec![1.]], order);
+    for (s, e) in s.iter().zip(expected.iter()) {
+        assert_eq!(s.len(), order + 1);
+        for (k, c) in s.iter().enumerate() {
+            let d = c * factorial(k);
+            assert!(
+                (d - e(k)).abs() < 1e-9 * (1. + e(k).abs()),
+                "{} {}",
+                d,
+                e(k)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_taylor_matches_nested_fwd() {
+    use crate::core::*;
+
+    let mut x = Leaf(0.3_f64);
+    x.active();
+    let f = Mul(
+        Tanh(x.clone()),
+        Atan2(Sinh(x.clone()), Add(Const(2.), Erf(x.clone()))),
+    );
+
+    //third derivative by nesting fwd
+    let d3 = f.fwd().fwd().fwd();
+    let s = taylor(std::slice::from_ref(&f), &[x], &[vec![1.]], 3);
+    assert!((eval(&[d3])[0] - 6. * s[0][3]).abs() < 1e-10);
+}
+
+#[test]
+fn test_taylor_custom_fallback() {
+    use crate::core::*;
+    use crate::custom::{Custom, Operation};
+
+    //op without Taylor rule, y = x^3
+    #[derive(Debug)]
+    struct Cube {}
+    impl Operation<f64> for Cube {
+        fn value(&self, inputs: &[f64]) -> f64 {
+            inputs[0].powi(3)
+        }
+        fn tangent(&self, inputs: &[PtrVWrap<f64>], tangents: &[PtrVWrap<f64>]) -> PtrVWrap<f64> {
+            let x = inputs[0].clone();
+            Mul(Mul(Const(3.), Mul(x.clone(), x)), tangents[0].clone())
+        }
+        fn adjoint(
+            &self,
+            inputs: &[PtrVWrap<f64>],
+            _output: &PtrVWrap<f64>,
+            out_adj: &PtrVWrap<f64>,
+        ) -> Vec<PtrVWrap<f64>> {
+            let x = inputs[0].clone();
+            vec![Mul(Mul(Const(3.), Mul(x.clone(), x)), out_adj.clone())]
+        }
+    }
+
+    //x(t) = 2 + t - t^2, compared with the built-in rules
+    let x = Leaf(2_f64);
+    let f = Sin(Custom(Cube {}, vec![x.clone()]));
+    let g = Sin(Mul(x.clone(), Mul(x.clone(), x.clone())));
+    let s = taylor(&[f, g], &[x], &[vec![1., -1.]], 4);
+    for (a, b) in s[0].iter().zip(s[1].iter()) {
+        assert!((a - b).abs() < 1e-9 * (1. + b.abs()));
+    }
+}
+
+#[test]
+fn test_taylor_custom_rule() {
+    use crate::core::*;
+    use crate::custom::{Custom, CustomGradient, GradientOverride, Operation};
+
+    //y = x^3 with a Taylor rule, the tangent rule must not be used
+    #[derive(Debug)]
+    struct Cube {}
+    impl Operation<f64> for Cube {
+        fn value(&self, inputs: &[f64]) -> f64 {
+            inputs[0].powi(3)
+        }
+        fn tangent(&self, _inputs: &[PtrVWrap<f64>], _tangents: &[PtrVWrap<f64>]) -> PtrVWrap<f64> {
+            panic!("tangent used in Taylor mode")
+        }
+        fn adjoint(
+            &self,
+            _inputs: &[PtrVWrap<f64>],
+            _output: &PtrVWrap<f64>,
+            _out_adj: &PtrVWrap<f64>,
+        ) -> Vec<PtrVWrap<f64>> {
+            unreachable!()
+        }
+        fn taylor_val(&self, inputs: &[Vec<f64>], _output: &f64) -> Option<Vec<f64>> {
+            Some(mul(&inputs[0], &mul(&inputs[0], &inputs[0])))
+        }
+    }
+
+    //identity derivative of the wrapped subgraph, the Taylor rule follows it
+    #[derive(Debug)]
+    struct StraightThrough {}
+    impl GradientOverride<f64> for StraightThrough {
+        fn tangent(
+            &self,
+            _inputs: &[PtrVWrap<f64>],
+            _output: &PtrVWrap<f64>,
+            _tangents: &[PtrVWrap<f64>],
+        ) -> PtrVWrap<f64> {
+            panic!("tangent used in Taylor mode")
+        }
+        fn adjoint(
+            &self,
+            _inputs: &[PtrVWrap<f64>],
+            _output: &PtrVWrap<f64>,
+            _out_adj: &PtrVWrap<f64>,
+        ) -> Vec<PtrVWrap<f64>> {
+            unreachable!()
+        }
+        fn taylor_val(&self, inputs: &[Vec<f64>], output: &f64) -> Option<Vec<f64>> {
+            let mut s = inputs[0].clone();
+            s[0] = *output;
+            Some(s)
+        }
+    }
+
+    //x(t) = 2 + t, x^3 = 8 + 12t + 6t^2 + t^3 at an order the fallback could not reach
+    let x = Leaf(2_f64);
+    let f = Custom(Cube {}, vec![x.clone()]);
+    let g = CustomGradient(StraightThrough {}, Sin(x.clone()), vec![x.clone()]);
+    let s = taylor(&[f, g], &[x], &[vec![1.]], 13);
+    let mut expected = vec![8., 12., 6., 1.];
+    expected.resize(14, 0.);
+    assert_eq!(s[0], expected);
+    let mut expected = vec![2_f64.sin(), 1.];
+    expected.resize(14, 0.);
+    assert_eq!(s[1], expected);
+}
+
+#[test]
+fn test_div_factorial() {
+    assert_eq!(div_factorial(&5_f64, 0), 5.);
+    assert_eq!(div_factorial(&5_f64, 1), 5.);
+    assert!((div_factorial(&24_f64, 4) - 1.).abs() < 1e-15);
+    //13! = 6227020800 no longer fits an i32
+    assert!((div_factorial(&6227020800_f64, 13) - 1.).abs() < 1e-12);
+    assert!((div_factorial(&1_f64, 20) * factorial(20) - 1.).abs() < 1e-12);
+}
+
+#[test]
+fn test_taylor_tensor() {
+    use crate::core::*;
+    use crate::valtype::ValType;
+    use ndarray::arr1;
+
+    let x = Leaf(ValType::from(arr1(&[0.1, 0.5, -0.3]).into_dyn()));
+    let v = ValType::from(arr1(&[1., -2., 0.5]).into_dyn());
+    let f = Sum(Mul(Sin(x.clone()), Transpose(x.clone())));
+
+    let s = taylor(
+        std::slice::from_ref(&f),
+        &[x.clone()],
+        &[vec![v.clone()]],
+        2,
+    );
+    let d1 = crate::jacobian::jvp(&[f], &[x], &[v]);
+    match (&s[0][1], &d1[0]) {
+        (ValType::T(a), ValType::T(b)) => assert!((a - b).iter().all(|d| d.abs() < 1e-12)),
+        (a, b) => panic!("unexpected {:?} {:?}", a, b),
+    }
+}
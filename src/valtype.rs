@@ -1,9 +1,14 @@
-#[derive(Debug, Clone, Copy)]
+use crate::value::Value;
+use ndarray::{ArrayD, Axis, Ix2, IxDyn, Zip};
+
+#[derive(Debug, Clone)]
 pub enum ValType {
     F(f32),
     D(f64),
     I(i32),
     L(i64),
+    /// dense tensor, operations on it are elementwise unless stated otherwise
+    T(ArrayD<f64>),
 }
 
 use std::fmt;
@@ -21,6 +26,511 @@ impl From<ValType> for f32 {
             ValType::D(x) => x as f32,
             ValType::I(x) => x as f32,
             ValType::L(x) => x as f32,
+            ValType::T(x) => {
+                assert_eq!(
+                    x.len(),
+                    1,
+                    "tensor of shape {:?} is not a scalar",
+                    x.shape()
+                );
+                *x.iter().next().unwrap() as f32
+            }
+        }
+    }
+}
+
+impl From<ValType> for f64 {
+    fn from(s: ValType) -> Self {
+        match s {
+            ValType::T(x) => {
+                assert_eq!(
+                    x.len(),
+                    1,
+                    "tensor of shape {:?} is not a scalar",
+                    x.shape()
+                );
+                *x.iter().next().unwrap()
+            }
+            x => x.scalar_f64(),
+        }
+    }
+}
+
+impl From<ArrayD<f64>> for ValType {
+    fn from(s: ArrayD<f64>) -> Self {
+        ValType::T(s)
+    }
+}
+
+impl ValType {
+    pub fn is_tensor(&self) -> bool {
+        matches!(self, ValType::T(_))
+    }
+
+    /// scalar value widened to f64, panics for tensors
+    pub fn scalar_f64(&self) -> f64 {
+        match self {
+            ValType::F(x) => *x as f64,
+            ValType::D(x) => *x,
+            ValType::I(x) => *x as f64,
+            ValType::L(x) => *x as f64,
+            ValType::T(_) => panic!("expected scalar, found tensor"),
+        }
+    }
+
+    /// tensor view of the value, scalars become 0-dimensional arrays
+    pub fn to_array(&self) -> ArrayD<f64> {
+        match self {
+            ValType::T(x) => x.clone(),
+            x => ArrayD::from_elem(IxDyn(&[]), x.scalar_f64()),
+        }
+    }
+
+    /// elementwise map of a tensor
+    pub(crate) fn map_tensor(&self, f: impl Fn(f64) -> f64) -> ValType {
+        match self {
+            ValType::T(x) => ValType::T(x.mapv(f)),
+            x => ValType::T(ArrayD::from_elem(IxDyn(&[]), f(x.scalar_f64()))),
+        }
+    }
+
+    /// elementwise combination where at least one side is a tensor,
+    /// operands are broadcast against each other following numpy rules
+    pub(crate) fn zip_tensor(&self, other: &ValType, f: impl Fn(f64, f64) -> f64) -> ValType {
+        match (self, other) {
+            (ValType::T(a), ValType::T(b)) => {
+                let shape = broadcast_shape(a.shape(), b.shape());
+                let mut c = a
+                    .broadcast(IxDyn(&shape))
+                    .expect("tensor shape mismatch")
+                    .to_owned();
+                Zip::from(&mut c)
+                    .and(b.broadcast(IxDyn(&shape)).expect("tensor shape mismatch"))
+                    .apply(|x, &y| *x = f(*x, y));
+                ValType::T(c)
+            }
+            (ValType::T(a), b) => {
+                let b = b.scalar_f64();
+                ValType::T(a.mapv(|x| f(x, b)))
+            }
+            (a, ValType::T(b)) => {
+                let a = a.scalar_f64();
+                ValType::T(b.mapv(|x| f(a, x)))
+            }
+            _ => panic!("expected tensor operand"),
+        }
+    }
+}
+
+/// shape resulting from broadcasting two shapes against each other,
+/// trailing axes are aligned and axes of length 1 are stretched
+pub(crate) fn broadcast_shape(a: &[usize], b: &[usize]) -> Vec<usize> {
+    let n = a.len().max(b.len());
+    let mut shape = vec![0; n];
+    for i in 0..n {
+        let da = if i < a.len() { a[a.len() - 1 - i] } else { 1 };
+        let db = if i < b.len() { b[b.len() - 1 - i] } else { 1 };
+        shape[n - 1 - i] = match (da, db) {
+            (x, y) if x == y => x,
+            (1, y) => y,
+            (x, 1) => x,
+            _ => panic!("cannot broadcast shapes {:?} and {:?}", a, b),
+        };
+    }
+    shape
+}
+
+/// type promotion
+///
+/// scalar types are ordered as I < L < D and I < F < D, mixing L and F gives D
+/// since f32 cannot hold i64 values, tensors are above all scalar types.
+/// operations closed over integers (add, mul) keep integer types,
+/// other operations first promote I to F and L to D.
+impl ValType {
+    /// combines two values after promoting them to a common type
+    pub(crate) fn zip(
+        &self,
+        other: &ValType,
+        fi: impl Fn(i32, i32) -> i32,
+        fl: impl Fn(i64, i64) -> i64,
+        ff: impl Fn(f32, f32) -> f32,
+        fd: impl Fn(f64, f64) -> f64,
+    ) -> ValType {
+        use ValType::*;
+        match (self, other) {
+            (T(_), _) | (_, T(_)) => self.zip_tensor(other, fd),
+            (D(_), _) | (_, D(_)) | (L(_), F(_)) | (F(_), L(_)) => {
+                D(fd(self.scalar_f64(), other.scalar_f64()))
+            }
+            (F(_), _) | (_, F(_)) => F(ff(self.scalar_f64() as f32, other.scalar_f64() as f32)),
+            (L(_), _) | (_, L(_)) => L(fl(self.scalar_i64(), other.scalar_i64())),
+            (I(a), I(b)) => I(fi(*a, *b)),
+        }
+    }
+
+    /// combines two values after promoting them to a common floating point type
+    pub(crate) fn zip_float(
+        &self,
+        other: &ValType,
+        ff: impl Fn(f32, f32) -> f32,
+        fd: impl Fn(f64, f64) -> f64,
+    ) -> ValType {
+        use ValType::*;
+        match (self, other) {
+            (T(_), _) | (_, T(_)) => self.zip_tensor(other, fd),
+            (D(_), _) | (_, D(_)) | (L(_), _) | (_, L(_)) => {
+                D(fd(self.scalar_f64(), other.scalar_f64()))
+            }
+            _ => F(ff(self.scalar_f64() as f32, other.scalar_f64() as f32)),
+        }
+    }
+
+    /// maps a value keeping its type
+    pub(crate) fn map(
+        &self,
+        fi: impl Fn(i32) -> i32,
+        fl: impl Fn(i64) -> i64,
+        ff: impl Fn(f32) -> f32,
+        fd: impl Fn(f64) -> f64,
+    ) -> ValType {
+        match self {
+            ValType::F(x) => ValType::F(ff(*x)),
+            ValType::I(x) => ValType::I(fi(*x)),
+            ValType::D(x) => ValType::D(fd(*x)),
+            ValType::L(x) => ValType::L(fl(*x)),
+            ValType::T(_) => self.map_tensor(fd),
+        }
+    }
+
+    /// maps a value after promoting it to a floating point type
+    pub(crate) fn map_float(&self, ff: impl Fn(f32) -> f32, fd: impl Fn(f64) -> f64) -> ValType {
+        match self {
+            ValType::F(x) => ValType::F(ff(*x)),
+            ValType::I(x) => ValType::F(ff(*x as f32)),
+            ValType::D(x) => ValType::D(fd(*x)),
+            ValType::L(x) => ValType::D(fd(*x as f64)),
+            ValType::T(_) => self.map_tensor(fd),
+        }
+    }
+
+    fn scalar_i64(&self) -> i64 {
+        match self {
+            ValType::I(x) => *x as i64,
+            ValType::L(x) => *x,
+            _ => panic!("expected integer"),
+        }
+    }
+}
+
+impl std::ops::Add for &ValType {
+    type Output = ValType;
+    fn add(self, other: &ValType) -> ValType {
+        self.zip(
+            other,
+            |a, b| a + b,
+            |a, b| a + b,
+            |a, b| a + b,
+            |a, b| a + b,
+        )
+    }
+}
+
+impl std::ops::Mul for &ValType {
+    type Output = ValType;
+    fn mul(self, other: &ValType) -> ValType {
+        self.zip(
+            other,
+            |a, b| a * b,
+            |a, b| a * b,
+            |a, b| a * b,
+            |a, b| a * b,
+        )
+    }
+}
+
+impl std::ops::Div for &ValType {
+    type Output = ValType;
+    fn div(self, other: &ValType) -> ValType {
+        self.zip_float(other, |a, b| a / b, |a, b| a / b)
+    }
+}
+
+/// arithmetic with type promotion, tensor operations on ndarray
+impl Value for ValType {
+    fn from_i32(v: i32) -> Self {
+        ValType::I(v)
+    }
+
+    fn from_f64(v: f64) -> Self {
+        ValType::D(v)
+    }
+
+    fn ones_like(&self) -> Self {
+        match self {
+            ValType::F(_) => ValType::F(1.),
+            ValType::D(_) => ValType::D(1.),
+            ValType::I(_) => ValType::I(1),
+            ValType::L(_) => ValType::L(1),
+            ValType::T(x) => ValType::T(ArrayD::ones(x.raw_dim())),
+        }
+    }
+
+    fn zeros_like(&self) -> Self {
+        match self {
+            ValType::F(_) => ValType::F(0.),
+            ValType::D(_) => ValType::D(0.),
+            ValType::I(_) => ValType::I(0),
+            ValType::L(_) => ValType::L(0),
+            ValType::T(x) => ValType::T(ArrayD::zeros(x.raw_dim())),
+        }
+    }
+
+    fn scalar_like(&self, v: f64) -> Self {
+        match self {
+            ValType::F(_) | ValType::I(_) => ValType::F(v as f32),
+            _ => ValType::D(v),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            ValType::T(x) => x.iter().all(|v| *v == 0.),
+            x => x.scalar_f64() == 0.,
+        }
+    }
+
+    fn is_one(&self) -> bool {
+        match self {
+            ValType::T(x) => x.iter().all(|v| *v == 1.),
+            x => x.scalar_f64() == 1.,
+        }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn div(&self, other: &Self) -> Self {
+        self / other
+    }
+
+    fn powf(&self, expo: &Self) -> Self {
+        self.zip_float(
+            expo,
+            |b, e| {
+                if e < 1e-15 && e > -1e-15 {
+                    1.
+                } else {
+                    b.powf(e)
+                }
+            },
+            |b, e| {
+                if e < 1e-15 && e > -1e-15 {
+                    1.
+                } else {
+                    b.powf(e)
+                }
+            },
+        )
+    }
+
+    fn sin(&self) -> Self {
+        self.map_float(f32::sin, f64::sin)
+    }
+
+    fn cos(&self) -> Self {
+        self.map_float(f32::cos, f64::cos)
+    }
+
+    fn tan(&self) -> Self {
+        self.map_float(f32::tan, f64::tan)
+    }
+
+    fn exp(&self) -> Self {
+        self.map_float(f32::exp, f64::exp)
+    }
+
+    fn ln(&self) -> Self {
+        self.map_float(f32::ln, f64::ln)
+    }
+
+    fn sqrt(&self) -> Self {
+        self.map_float(f32::sqrt, f64::sqrt)
+    }
+
+    fn abs(&self) -> Self {
+        self.map(i32::abs, i64::abs, f32::abs, f64::abs)
+    }
+
+    fn signum(&self) -> Self {
+        self.map(
+            i32::signum,
+            i64::signum,
+            |x| if x == 0. { 0. } else { x.signum() },
+            |x| if x == 0. { 0. } else { x.signum() },
+        )
+    }
+
+    fn tanh(&self) -> Self {
+        self.map_float(f32::tanh, f64::tanh)
+    }
+
+    fn sinh(&self) -> Self {
+        self.map_float(f32::sinh, f64::sinh)
+    }
+
+    fn cosh(&self) -> Self {
+        self.map_float(f32::cosh, f64::cosh)
+    }
+
+    fn asin(&self) -> Self {
+        self.map_float(f32::asin, f64::asin)
+    }
+
+    fn acos(&self) -> Self {
+        self.map_float(f32::acos, f64::acos)
+    }
+
+    fn atan(&self) -> Self {
+        self.map_float(f32::atan, f64::atan)
+    }
+
+    fn atan2(&self, other: &Self) -> Self {
+        self.zip_float(other, f32::atan2, f64::atan2)
+    }
+
+    fn log10(&self) -> Self {
+        self.map_float(f32::log10, f64::log10)
+    }
+
+    fn log2(&self) -> Self {
+        self.map_float(f32::log2, f64::log2)
+    }
+
+    fn erf(&self) -> Self {
+        self.map_float(libm::erff, libm::erf)
+    }
+
+    fn max(&self, other: &Self) -> Self {
+        self.zip(other, i32::max, i64::max, f32::max, f64::max)
+    }
+
+    fn min(&self, other: &Self) -> Self {
+        self.zip(other, i32::min, i64::min, f32::min, f64::min)
+    }
+
+    fn neg(&self) -> Self {
+        self.map(|x| -x, |x| -x, |x| -x, |x| -x)
+    }
+
+    fn sub(&self, other: &Self) -> Self {
+        self.zip(
+            other,
+            |a, b| a - b,
+            |a, b| a - b,
+            |a, b| a - b,
+            |a, b| a - b,
+        )
+    }
+
+    fn shape(&self) -> Vec<usize> {
+        match self {
+            ValType::T(x) => x.shape().to_vec(),
+            _ => vec![],
+        }
+    }
+
+    fn matmul(&self, other: &Self) -> Self {
+        match (self, other) {
+            (ValType::T(a), ValType::T(b)) if a.ndim() == 2 && b.ndim() == 2 => {
+                let a = a
+                    .view()
+                    .into_dimensionality::<Ix2>()
+                    .expect("matmul expects 2-dimensional tensors");
+                let b = b
+                    .view()
+                    .into_dimensionality::<Ix2>()
+                    .expect("matmul expects 2-dimensional tensors");
+                assert_eq!(a.cols(), b.rows(), "matmul shape mismatch");
+                ValType::T(a.dot(&b).into_dyn())
+            }
+            (a, b) if a.shape().is_empty() && b.shape().is_empty() => a.mul(b),
+            (a, b) => panic!(
+                "matmul expects two 2-dimensional tensors or two scalars, got shapes {:?} and {:?}, \
+                 reshape vectors to a column or a row",
+                a.shape(),
+                b.shape()
+            ),
+        }
+    }
+
+    fn transpose(&self) -> Self {
+        match self {
+            ValType::T(x) => ValType::T(x.clone().reversed_axes()),
+            x => x.clone(),
+        }
+    }
+
+    fn sum(&self, axis: Option<usize>) -> Self {
+        match (self, axis) {
+            (ValType::T(x), None) => ValType::T(ArrayD::from_elem(IxDyn(&[]), x.sum())),
+            (ValType::T(x), Some(ax)) => ValType::T(x.sum_axis(Axis(ax))),
+            (x, _) => x.clone(),
+        }
+    }
+
+    fn count(&self, axis: Option<usize>) -> usize {
+        match (self, axis) {
+            (ValType::T(x), None) => x.len(),
+            (ValType::T(x), Some(ax)) => x.len_of(Axis(ax)),
+            _ => 1,
+        }
+    }
+
+    fn insert_axis(&self, axis: usize) -> Self {
+        ValType::T(self.to_array().insert_axis(Axis(axis)))
+    }
+
+    fn reshape(&self, shape: &[usize]) -> Self {
+        let x = self.to_array();
+        ValType::T(
+            ArrayD::from_shape_vec(IxDyn(shape), x.iter().cloned().collect())
+                .unwrap_or_else(|_| panic!("cannot reshape {:?} into {:?}", x.shape(), shape)),
+        )
+    }
+
+    fn broadcast_to(&self, shape: &[usize]) -> Self {
+        if !self.is_tensor() && shape.is_empty() {
+            return self.clone();
+        }
+        let x = self.to_array();
+        let b = x
+            .broadcast(IxDyn(shape))
+            .unwrap_or_else(|| panic!("cannot broadcast {:?} into {:?}", x.shape(), shape))
+            .to_owned();
+        ValType::T(b)
+    }
+
+    fn sum_to(&self, shape: &[usize]) -> Self {
+        match self {
+            ValType::T(x) => {
+                let mut x = x.clone();
+                while x.ndim() > shape.len() {
+                    x = x.sum_axis(Axis(0));
+                }
+                for (ax, &n) in shape.iter().enumerate() {
+                    if n == 1 && x.len_of(Axis(ax)) != 1 {
+                        x = x.sum_axis(Axis(ax)).insert_axis(Axis(ax));
+                    }
+                }
+                assert_eq!(x.shape(), shape, "cannot reduce to shape {:?}", shape);
+                ValType::T(x)
+            }
+            x => x.broadcast_to(shape),
         }
     }
 }
@@ -0,0 +1,244 @@
+//! Numeric type carried by graph nodes
+//!
+//! Operations only rely on this trait, so a graph can hold ValType
+//! (runtime dispatched scalars and tensors) or any num_traits::Float type
+//! such as f32, f64 or user defined dual numbers, intervals, fixed-point types.
+
+use num_traits::Float;
+use std::fmt::Debug;
+
+pub trait Value: Clone + Debug + 'static {
+    /// small integral constants used inside derivative rules
+    fn from_i32(v: i32) -> Self;
+
+    /// scalar constants written in expressions
+    fn from_f64(v: f64) -> Self;
+
+    /// 1 with the same type and shape as the current value
+    fn ones_like(&self) -> Self;
+
+    /// 0 with the same type and shape as the current value
+    fn zeros_like(&self) -> Self;
+
+    /// scalar constant in the floating point type the value promotes to,
+    /// used for irrational constants inside derivative rules
+    fn scalar_like(&self, v: f64) -> Self;
+
+    /// true if the value is known to be 0 everywhere, used to simplify graphs
+    fn is_zero(&self) -> bool {
+        false
+    }
+
+    /// true if the value is known to be 1 everywhere, used to simplify graphs
+    fn is_one(&self) -> bool {
+        false
+    }
+
+    fn add(&self, other: &Self) -> Self;
+    fn mul(&self, other: &Self) -> Self;
+    fn div(&self, other: &Self) -> Self;
+    fn powf(&self, expo: &Self) -> Self;
+    fn sin(&self) -> Self;
+    fn cos(&self) -> Self;
+    fn tan(&self) -> Self;
+    fn exp(&self) -> Self;
+    fn ln(&self) -> Self;
+    fn sqrt(&self) -> Self;
+    fn abs(&self) -> Self;
+    /// -1, 0 or 1
+    fn signum(&self) -> Self;
+    fn tanh(&self) -> Self;
+    fn sinh(&self) -> Self;
+    fn cosh(&self) -> Self;
+    fn asin(&self) -> Self;
+    fn acos(&self) -> Self;
+    fn atan(&self) -> Self;
+    /// four quadrant arctangent of self/other
+    fn atan2(&self, other: &Self) -> Self;
+    fn log10(&self) -> Self;
+    fn log2(&self) -> Self;
+    fn erf(&self) -> Self;
+    fn max(&self, other: &Self) -> Self;
+    fn min(&self, other: &Self) -> Self;
+
+    fn neg(&self) -> Self {
+        self.mul(&Self::from_i32(-1))
+    }
+
+    fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    /// 1/(1+exp(-x)), written with tanh to avoid overflow
+    fn sigmoid(&self) -> Self {
+        let half = self.scalar_like(0.5);
+        half.mul(&Self::from_i32(1).add(&self.mul(&half).tanh()))
+    }
+
+    /// ln(1+exp(x)), written as max(x,0)+ln(1+exp(-|x|)) to avoid overflow
+    fn softplus(&self) -> Self {
+        let one = Self::from_i32(1);
+        self.max(&self.zeros_like())
+            .add(&one.add(&self.abs().neg().exp()).ln())
+    }
+
+    /// shape of the value, scalars have an empty shape
+    fn shape(&self) -> Vec<usize> {
+        vec![]
+    }
+
+    /// matrix product of 2-dimensional tensors, or product of two scalars
+    fn matmul(&self, other: &Self) -> Self {
+        self.mul(other)
+    }
+
+    /// reverses the order of axes
+    fn transpose(&self) -> Self {
+        self.clone()
+    }
+
+    /// sum over all elements or over the given axis
+    fn sum(&self, _axis: Option<usize>) -> Self {
+        self.clone()
+    }
+
+    /// number of elements reduced by a sum over all elements or over the given axis
+    fn count(&self, _axis: Option<usize>) -> usize {
+        1
+    }
+
+    /// inserts a new axis of length 1
+    fn insert_axis(&self, _axis: usize) -> Self {
+        self.clone()
+    }
+
+    /// reshape in row-major order of elements
+    fn reshape(&self, shape: &[usize]) -> Self {
+        assert_eq!(
+            shape.iter().product::<usize>(),
+            1,
+            "cannot reshape scalar into {:?}",
+            shape
+        );
+        self.clone()
+    }
+
+    /// repeats the value along new leading axes and length-1 axes to match the shape
+    fn broadcast_to(&self, _shape: &[usize]) -> Self {
+        self.clone()
+    }
+
+    /// sums over leading axes and length-1 axes to reduce the value to the shape,
+    /// inverse of broadcast_to
+    fn sum_to(&self, _shape: &[usize]) -> Self {
+        self.clone()
+    }
+}
+
+/// scalar floating point types, tensor operations use the scalar defaults
+impl<T: Float + Debug + 'static> Value for T {
+    fn from_i32(v: i32) -> Self {
+        T::from(v).expect("constant not representable")
+    }
+    fn from_f64(v: f64) -> Self {
+        T::from(v).expect("constant not representable")
+    }
+    fn ones_like(&self) -> Self {
+        T::one()
+    }
+    fn zeros_like(&self) -> Self {
+        T::zero()
+    }
+    fn scalar_like(&self, v: f64) -> Self {
+        T::from(v).expect("constant not representable")
+    }
+    fn is_zero(&self) -> bool {
+        *self == T::zero()
+    }
+    fn is_one(&self) -> bool {
+        *self == T::one()
+    }
+    fn add(&self, other: &Self) -> Self {
+        *self + *other
+    }
+    fn mul(&self, other: &Self) -> Self {
+        *self * *other
+    }
+    fn div(&self, other: &Self) -> Self {
+        *self / *other
+    }
+    fn powf(&self, expo: &Self) -> Self {
+        Float::powf(*self, *expo)
+    }
+    fn sin(&self) -> Self {
+        Float::sin(*self)
+    }
+    fn cos(&self) -> Self {
+        Float::cos(*self)
+    }
+    fn tan(&self) -> Self {
+        Float::tan(*self)
+    }
+    fn exp(&self) -> Self {
+        Float::exp(*self)
+    }
+    fn ln(&self) -> Self {
+        Float::ln(*self)
+    }
+    fn sqrt(&self) -> Self {
+        Float::sqrt(*self)
+    }
+    fn abs(&self) -> Self {
+        Float::abs(*self)
+    }
+    fn signum(&self) -> Self {
+        if *self == T::zero() {
+            *self
+        } else {
+            Float::signum(*self)
+        }
+    }
+    fn tanh(&self) -> Self {
+        Float::tanh(*self)
+    }
+    fn sinh(&self) -> Self {
+        Float::sinh(*self)
+    }
+    fn cosh(&self) -> Self {
+        Float::cosh(*self)
+    }
+    fn asin(&self) -> Self {
+        Float::asin(*self)
+    }
+    fn acos(&self) -> Self {
+        Float::acos(*self)
+    }
+    fn atan(&self) -> Self {
+        Float::atan(*self)
+    }
+    fn atan2(&self, other: &Self) -> Self {
+        Float::atan2(*self, *other)
+    }
+    fn log10(&self) -> Self {
+        Float::log10(*self)
+    }
+    fn log2(&self) -> Self {
+        Float::log2(*self)
+    }
+    fn erf(&self) -> Self {
+        let x = self.to_f64().expect("value not representable as f64");
+        T::from(libm::erf(x)).expect("value not representable")
+    }
+    fn max(&self, other: &Self) -> Self {
+        Float::max(*self, *other)
+    }
+    fn min(&self, other: &Self) -> Self {
+        Float::min(*self, *other)
+    }
+    fn neg(&self) -> Self {
+        -*self
+    }
+    fn sub(&self, other: &Self) -> Self {
+        *self - *other
+    }
+}